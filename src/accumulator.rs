@@ -0,0 +1,344 @@
+use serde::{Deserialize, Serialize};
+
+use std::sync::{Arc, Mutex};
+
+use crate::buffer::Buffer;
+use crate::counter::Counter;
+use crate::error::Error;
+
+/// Rows of pixels behind one lock of a [`ShardedBuffer`]
+const SHARD_ROWS: usize = 16;
+/// Number of hits a worker collects for one shard before taking its lock
+const SHARD_BATCH: usize = 256;
+
+/// How the worker threads add up their hits
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Accumulation {
+    /// Every thread has its own buffers, which are summed after every round. Fastest, but needs
+    /// memory for all buffers once per thread.
+    #[default]
+    PerThread,
+    /// All threads add to the same buffers of atomic counters
+    Atomic,
+    /// All threads add to the same buffers, split into strips of rows behind locks. Hits are
+    /// collected per strip first so every lock is taken once per batch.
+    Sharded,
+}
+
+/// Hit counts of one band that all worker threads add to at once
+pub struct AtomicBuffer<C: Counter> {
+    width: usize,
+    height: usize,
+    buffer: Vec<C::Atomic>,
+}
+impl<C: Counter> AtomicBuffer<C> {
+    pub fn new(width: usize, height: usize) -> AtomicBuffer<C> {
+        AtomicBuffer {
+            width,
+            height,
+            buffer: (0..width * height).map(|_| C::new_atomic()).collect(),
+        }
+    }
+
+    pub fn add(&self, x: usize, y: usize, amount: C) {
+        C::add_atomic(&self.buffer[y * self.width + x], amount);
+    }
+
+    /// Adds the hits to `buffer` and resets them. No thread may add hits at the same time.
+    pub fn drain_into(&self, buffer: &mut Buffer<C>) -> Result<(), Error> {
+        check_dimensions(buffer, self.width, self.height)?;
+        for (x, hits) in buffer.buffer_mut().iter_mut().zip(&self.buffer) {
+            *x = x.add_saturating(C::take_atomic(hits));
+        }
+        Ok(())
+    }
+}
+
+/// Hit counts of one band that all worker threads add to, split into strips of
+/// [`SHARD_ROWS`] rows that are locked separately
+pub struct ShardedBuffer<C> {
+    width: usize,
+    height: usize,
+    shards: Vec<Mutex<Vec<C>>>,
+}
+impl<C: Counter> ShardedBuffer<C> {
+    pub fn new(width: usize, height: usize) -> ShardedBuffer<C> {
+        ShardedBuffer {
+            width,
+            height,
+            shards: (0..height.div_ceil(SHARD_ROWS))
+                .map(|shard| {
+                    let rows = SHARD_ROWS.min(height - shard * SHARD_ROWS);
+                    Mutex::new(vec![C::default(); rows * width])
+                })
+                .collect(),
+        }
+    }
+
+    /// Shard containing the pixel and its index within the shard
+    fn locate(&self, x: usize, y: usize) -> (usize, u32) {
+        (y / SHARD_ROWS, ((y % SHARD_ROWS) * self.width + x) as u32)
+    }
+
+    /// Adds hits given as index within `shard` and amount
+    pub fn add_batch(&self, shard: usize, hits: &[(u32, C)]) {
+        let mut shard = self.shards[shard].lock().expect("Shard poisoned");
+        for (i, amount) in hits {
+            let x = &mut shard[*i as usize];
+            *x = x.add_saturating(*amount);
+        }
+    }
+
+    /// Adds the hits to `buffer` and resets them. No thread may add hits at the same time.
+    pub fn drain_into(&self, buffer: &mut Buffer<C>) -> Result<(), Error> {
+        check_dimensions(buffer, self.width, self.height)?;
+        let pixels = buffer.buffer_mut().chunks_mut(SHARD_ROWS * self.width);
+        for (pixels, shard) in pixels.zip(&self.shards) {
+            let mut shard = shard.lock().expect("Shard poisoned");
+            for (x, hits) in pixels.iter_mut().zip(shard.iter_mut()) {
+                *x = x.add_saturating(std::mem::take(hits));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Buffers of all bands shared by the worker threads
+pub enum SharedBuffers<C: Counter> {
+    Atomic(Vec<AtomicBuffer<C>>),
+    Sharded(Vec<ShardedBuffer<C>>),
+}
+impl<C: Counter> SharedBuffers<C> {
+    /// Shared buffers for `bands` bands, or `None` if every thread accumulates on its own
+    pub fn new(
+        accumulation: Accumulation,
+        width: usize,
+        height: usize,
+        bands: usize,
+    ) -> Option<SharedBuffers<C>> {
+        match accumulation {
+            Accumulation::PerThread => None,
+            Accumulation::Atomic => Some(SharedBuffers::Atomic(
+                (0..bands)
+                    .map(|_| AtomicBuffer::new(width, height))
+                    .collect(),
+            )),
+            Accumulation::Sharded => Some(SharedBuffers::Sharded(
+                (0..bands)
+                    .map(|_| ShardedBuffer::new(width, height))
+                    .collect(),
+            )),
+        }
+    }
+
+    /// Adds the hits of every band to `buffers` and resets them
+    pub fn drain_into<'a>(
+        &self,
+        buffers: impl IntoIterator<Item = &'a mut Buffer<C>>,
+    ) -> Result<(), Error> {
+        for (band, buffer) in buffers.into_iter().enumerate() {
+            match self {
+                SharedBuffers::Atomic(shared) => shared[band].drain_into(buffer)?,
+                SharedBuffers::Sharded(shared) => shared[band].drain_into(buffer)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Where a worker thread adds the hits of its orbits
+pub enum Accumulator<C: Counter> {
+    /// The worker's own buffers, one per band
+    Local(Vec<Buffer<C>>),
+    Shared {
+        buffers: Arc<SharedBuffers<C>>,
+        /// Hits waiting to be added to a [`ShardedBuffer`], per band and shard
+        pending: Vec<Vec<Vec<(u32, C)>>>,
+    },
+}
+impl<C: Counter> Accumulator<C> {
+    pub fn new(
+        shared: Option<&Arc<SharedBuffers<C>>>,
+        width: usize,
+        height: usize,
+        bands: usize,
+    ) -> Accumulator<C> {
+        match shared {
+            None => Accumulator::Local(
+                (0..bands)
+                    .map(|_| Buffer::new_default(width, height))
+                    .collect(),
+            ),
+            Some(shared) => {
+                let pending = match &**shared {
+                    SharedBuffers::Atomic(_) => Vec::new(),
+                    SharedBuffers::Sharded(buffers) => buffers
+                        .iter()
+                        .map(|b| vec![Vec::with_capacity(SHARD_BATCH); b.shards.len()])
+                        .collect(),
+                };
+                Accumulator::Shared {
+                    buffers: shared.clone(),
+                    pending,
+                }
+            }
+        }
+    }
+
+    pub fn add(&mut self, band: usize, x: usize, y: usize, amount: C) {
+        match self {
+            Accumulator::Local(buffers) => {
+                let buffer = &mut buffers[band];
+                buffer.set(x, y, buffer.get(x, y).add_saturating(amount));
+            }
+            Accumulator::Shared { buffers, pending } => match &**buffers {
+                SharedBuffers::Atomic(shared) => shared[band].add(x, y, amount),
+                SharedBuffers::Sharded(shared) => {
+                    let (shard, i) = shared[band].locate(x, y);
+                    let hits = &mut pending[band][shard];
+                    hits.push((i, amount));
+                    if hits.len() >= SHARD_BATCH {
+                        shared[band].add_batch(shard, hits);
+                        hits.clear();
+                    }
+                }
+            },
+        }
+    }
+
+    /// Adds all hits that are still held back to the shared buffers
+    pub fn flush(&mut self) {
+        if let Accumulator::Shared { buffers, pending } = self {
+            if let SharedBuffers::Sharded(shared) = &**buffers {
+                for (buffer, pending) in shared.iter().zip(pending) {
+                    for (shard, hits) in pending.iter_mut().enumerate() {
+                        if !hits.is_empty() {
+                            buffer.add_batch(shard, hits);
+                            hits.clear();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Adds the hits of the worker's own buffers to `buffers` and resets them. Shared buffers
+    /// are drained with [`SharedBuffers::drain_into`] instead.
+    pub fn drain_into<'a>(
+        &mut self,
+        buffers: impl IntoIterator<Item = &'a mut Buffer<C>>,
+    ) -> Result<(), Error> {
+        if let Accumulator::Local(local) = self {
+            for (buffer, local) in buffers.into_iter().zip(local) {
+                buffer.check_dimensions(local)?;
+                for (x, hits) in buffer.buffer_mut().iter_mut().zip(local.buffer_mut()) {
+                    *x = x.add_saturating(std::mem::take(hits));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_dimensions<C>(buffer: &Buffer<C>, width: usize, height: usize) -> Result<(), Error> {
+    if buffer.width() != width || buffer.height() != height {
+        Err(Error::DimensionMismatch {
+            expected: (buffer.width(), buffer.height()),
+            found: (width, height),
+        })
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Adds the same hits from four threads, each pixel gets `y + 1` from every thread
+    fn accumulate(accumulation: Accumulation, width: usize, height: usize) -> Vec<Buffer<u32>> {
+        let shared = SharedBuffers::new(accumulation, width, height, 2).map(Arc::new);
+        let accumulators = std::thread::scope(|s| {
+            let threads = (0..4)
+                .map(|_| {
+                    let shared = shared.as_ref();
+                    s.spawn(move || {
+                        let mut accumulator = Accumulator::new(shared, width, height, 2);
+                        for y in 0..height {
+                            for x in 0..width {
+                                accumulator.add(1, x, y, y as u32 + 1);
+                            }
+                        }
+                        accumulator.flush();
+                        accumulator
+                    })
+                })
+                .collect::<Vec<_>>();
+            threads
+                .into_iter()
+                .map(|t| t.join().unwrap())
+                .collect::<Vec<_>>()
+        });
+
+        let mut buffers = (0..2)
+            .map(|_| Buffer::new_default(width, height))
+            .collect::<Vec<_>>();
+        for mut accumulator in accumulators {
+            accumulator.drain_into(&mut buffers).unwrap();
+        }
+        if let Some(shared) = shared {
+            shared.drain_into(&mut buffers).unwrap();
+        }
+        buffers
+    }
+
+    #[test]
+    fn backends_add_up_the_same_hits() {
+        // A height that does not fill the last shard
+        let (width, height) = (13, SHARD_ROWS * 2 + 5);
+        for accumulation in [
+            Accumulation::PerThread,
+            Accumulation::Atomic,
+            Accumulation::Sharded,
+        ] {
+            let buffers = accumulate(accumulation, width, height);
+            assert!(buffers[0].buffer().iter().all(|&h| h == 0));
+            for y in 0..height {
+                for x in 0..width {
+                    assert_eq!(
+                        *buffers[1].get(x, y),
+                        4 * (y as u32 + 1),
+                        "{:?}",
+                        accumulation
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn draining_resets_the_hits() {
+        for accumulation in [Accumulation::Atomic, Accumulation::Sharded] {
+            let shared = SharedBuffers::<u32>::new(accumulation, 4, 4, 1).unwrap();
+            let mut accumulator = Accumulator::new(Some(&Arc::new(shared)), 4, 4, 1);
+            accumulator.add(0, 1, 2, 5);
+            accumulator.flush();
+            let shared = match accumulator {
+                Accumulator::Shared { buffers, .. } => buffers,
+                Accumulator::Local(_) => unreachable!(),
+            };
+
+            let mut buffer = Buffer::new_default(4, 4);
+            shared.drain_into([&mut buffer]).unwrap();
+            shared.drain_into([&mut buffer]).unwrap();
+            assert_eq!(*buffer.get(1, 2), 5);
+
+            let mut other = Buffer::new_default(4, 5);
+            assert!(matches!(
+                shared.drain_into([&mut other]),
+                Err(Error::DimensionMismatch { .. })
+            ));
+        }
+    }
+}
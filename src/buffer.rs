@@ -1,9 +1,14 @@
 use palette::{FromColor, Mix};
 
+use std::fs;
 use std::fs::File;
-use std::io;
-use std::io::{BufReader, BufWriter, Read, Write};
+use std::io::{BufReader, BufWriter, Seek};
 use std::ops::{Add, AddAssign};
+use std::path::Path;
+
+use crate::counter::Counter;
+use crate::error::Error;
+use crate::header::{Element, ElementType, Header};
 
 pub struct Buffer<T> {
     width: usize,
@@ -20,6 +25,15 @@ impl<T: Default + Clone> Buffer<T> {
     }
 }
 impl<T> Buffer<T> {
+    pub(crate) fn from_vec(width: usize, height: usize, buffer: Vec<T>) -> Buffer<T> {
+        debug_assert_eq!(buffer.len(), width * height);
+        Buffer {
+            width,
+            height,
+            buffer,
+        }
+    }
+
     pub fn get(&self, x: usize, y: usize) -> &T {
         &self.buffer[y * self.width + x]
     }
@@ -30,6 +44,9 @@ impl<T> Buffer<T> {
     pub fn buffer(&self) -> &Vec<T> {
         &self.buffer
     }
+    pub fn buffer_mut(&mut self) -> &mut Vec<T> {
+        &mut self.buffer
+    }
     pub fn width(&self) -> usize {
         self.width
     }
@@ -46,24 +63,37 @@ impl<T: Clone> Clone for Buffer<T> {
         }
     }
 }
+impl<T> Buffer<T> {
+    pub fn check_dimensions<U>(&self, other: &Buffer<U>) -> Result<(), Error> {
+        if self.width != other.width || self.height != other.height {
+            Err(Error::DimensionMismatch {
+                expected: (self.width, self.height),
+                found: (other.width, other.height),
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
 // TODO is it possible to remove the Copy bound here?
 // TODO implement different variants for references
-impl<T: AddAssign + Copy> AddAssign for Buffer<T> {
-    fn add_assign(&mut self, other: Buffer<T>) {
-        assert!(self.width == other.width);
-        assert!(self.height == other.height);
+impl<T: AddAssign + Copy> Buffer<T> {
+    /// Adds `other` element-wise, which fails if the dimensions differ
+    pub fn try_add_assign(&mut self, other: Buffer<T>) -> Result<(), Error> {
+        self.check_dimensions(&other)?;
 
-        for i in 0..self.buffer.len() {
-            self.buffer[i] += other.buffer[i];
+        for (x, y) in self.buffer.iter_mut().zip(other.buffer) {
+            *x += y;
         }
+        Ok(())
     }
 }
 
 impl<U, T: Add<T, Output = U>> Add for Buffer<T> {
-    type Output = Buffer<U>;
-    fn add(mut self, mut other: Buffer<T>) -> Buffer<U> {
-        assert!(self.width() == other.width && self.height == other.height);
-        Buffer {
+    type Output = Result<Buffer<U>, Error>;
+    fn add(mut self, mut other: Buffer<T>) -> Result<Buffer<U>, Error> {
+        self.check_dimensions(&other)?;
+        Ok(Buffer {
             width: self.width,
             height: self.height,
             buffer: self
@@ -72,64 +102,139 @@ impl<U, T: Add<T, Output = U>> Add for Buffer<T> {
                 .zip(other.buffer.drain(..))
                 .map(|(x, y)| x + y)
                 .collect(),
+        })
+    }
+}
+
+impl<T: Element> Buffer<T> {
+    /// Writes the buffer to a temporary file first and moves it in place afterwards, so an
+    /// interrupted write never leaves a truncated file behind
+    pub fn store(&self, header: &Header, file: impl AsRef<Path>) -> Result<(), Error> {
+        let file = file.as_ref();
+        let mut temp = file.as_os_str().to_owned();
+        temp.push(".tmp");
+
+        let mut f = BufWriter::new(File::create(&temp)?);
+        header.write(&mut f, T::TYPE, self.width, self.height)?;
+        for i in &self.buffer {
+            i.write(&mut f)?;
         }
+        f.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(&temp, file)?;
+
+        Ok(())
     }
+
+    pub fn load(file: impl AsRef<Path>) -> Result<(Header, Buffer<T>), Error> {
+        let file = File::open(file)?;
+        let file_size = file.metadata()?.len();
+        let mut f = BufReader::new(file);
+        let (header, element_type, width, height) = Header::read(&mut f)?;
+
+        // Files of u32 counts from before other element types existed are converted
+        let convert = element_type == ElementType::U32 && T::TYPE != ElementType::U32;
+        if element_type != T::TYPE && !convert {
+            return Err(Error::ElementTypeMismatch {
+                expected: T::TYPE,
+                found: element_type,
+            });
+        }
+        let expected = if convert {
+            data_size::<u32>(width, height)?
+        } else {
+            data_size::<T>(width, height)?
+        };
+        let found = file_size - f.stream_position()?;
+        if expected != found {
+            return Err(Error::FileSize { expected, found });
+        }
+
+        let len = width * height;
+        let mut buffer = Vec::with_capacity(len);
+        for _ in 0..len {
+            buffer.push(if convert {
+                T::from_u32(u32::read(&mut f)?)
+            } else {
+                T::read(&mut f)?
+            });
+        }
+
+        Ok((
+            header,
+            Buffer {
+                width,
+                height,
+                buffer,
+            },
+        ))
+    }
+}
+
+/// Number of bytes of the elements of a `width`×`height` buffer
+pub(crate) fn data_size<T: Element>(width: usize, height: usize) -> Result<u64, Error> {
+    width
+        .checked_mul(height)
+        .and_then(|len| len.checked_mul(T::SIZE))
+        .map(|size| size as u64)
+        .ok_or_else(|| Error::InvalidHeader(format!("invalid size {}x{}", width, height)))
 }
 
 impl Buffer<u32> {
     pub fn to_u8(&self) -> Buffer<u8> {
-        let max = self.buffer.iter().max().expect("");
+        let max = self.buffer.iter().max().copied().unwrap_or(0).max(1) as u64;
         Buffer {
             width: self.width,
             height: self.height,
             buffer: self
                 .buffer
                 .iter()
-                .map(|x| (x * 255 / max) as u8)
+                .map(|x| (*x as u64 * 255 / max) as u8)
                 .collect::<Vec<_>>(),
         }
     }
+}
+impl<T: Counter> Buffer<T> {
+    /// Largest count, or zero for an empty buffer
+    pub fn max(&self) -> T {
+        self.buffer
+            .iter()
+            .fold(T::default(), |max, x| if *x > max { *x } else { max })
+    }
+
     pub fn to_f32(&self) -> Buffer<f32> {
-        let max = *self.buffer.iter().max().expect("") as f32;
+        self.normalize(self.max())
+    }
+    /// Divides by `max`, e.g. the largest value of the whole buffer if this is only a strip of it
+    pub fn normalize(&self, max: T) -> Buffer<f32> {
+        let max = if max > T::default() {
+            max.to_f64()
+        } else {
+            1.0
+        };
         Buffer {
             width: self.width,
             height: self.height,
             buffer: self
                 .buffer
                 .iter()
-                .map(|x| *x as f32 / max)
+                .map(|x| (x.to_f64() / max) as f32)
                 .collect::<Vec<_>>(),
         }
     }
 
-    pub fn store(&self, file: &str) -> Result<(), io::Error> {
-        let mut f = BufWriter::new(File::create(file)?);
-        for i in &self.buffer {
-            f.write_all(&i.to_le_bytes())?;
-        }
+    /// Adds `other` element-wise without overflowing, which fails if the dimensions differ
+    pub fn saturating_add_assign(&mut self, other: &Buffer<T>) -> Result<(), Error> {
+        self.check_dimensions(other)?;
 
+        for (x, y) in self.buffer.iter_mut().zip(&other.buffer) {
+            *x = x.add_saturating(*y);
+        }
         Ok(())
     }
 
-    pub fn load(width: usize, height: usize, file: &str) -> Result<Buffer<u32>, io::Error> {
-        let mut f = BufReader::new(File::open(file)?);
-        let mut buffer = Vec::with_capacity(width * height);
-
-        let mut temp = [0u8; 4];
-        while let Ok(()) = f.read_exact(&mut temp) {
-            buffer.push(u32::from_le_bytes(temp));
-        }
-
-        if buffer.len() != width * height {
-            // TODO replace this with result
-            panic!("Wrong file size");
-        }
-
-        Ok(Buffer {
-            width,
-            height,
-            buffer,
-        })
+    /// Number of counts that reached the largest value of the counter type
+    pub fn saturated(&self) -> usize {
+        self.buffer.iter().filter(|x| x.is_saturated()).count()
     }
 }
 impl Buffer<f32> {
@@ -142,11 +247,7 @@ impl Buffer<f32> {
         Buffer {
             width: self.width,
             height: self.height,
-            buffer: self
-                .buffer
-                .iter()
-                .map(|x| (*x as f32).powf(1.0 / a))
-                .collect(),
+            buffer: self.buffer.iter().map(|x| x.powf(1.0 / a)).collect(),
         }
     }
 
@@ -215,8 +316,8 @@ impl Buffer<palette::Alpha<palette::Lab, f32>> {
         let mut result = Vec::new();
         for i in 0..b[0].buffer.len() {
             let mut color = b[0].buffer[i];
-            for j in 1..b.len() {
-                color = b[j].buffer[i].mix(&color, 1.0 / (j as f32 + 1.0));
+            for (j, other) in b.iter().enumerate().skip(1) {
+                color = other.buffer[i].mix(&color, 1.0 / (j as f32 + 1.0));
             }
 
             result.push(color);
@@ -250,17 +351,23 @@ impl Buffer<palette::Alpha<palette::Lab, f32>> {
     }
 }
 impl Buffer<(u8, u8, u8)> {
-    pub fn join(b1: Buffer<u8>, b2: Buffer<u8>, b3: Buffer<u8>) -> Buffer<(u8, u8, u8)> {
-        // TODO ensure sizes work
+    pub fn join(
+        b1: Buffer<u8>,
+        b2: Buffer<u8>,
+        b3: Buffer<u8>,
+    ) -> Result<Buffer<(u8, u8, u8)>, Error> {
+        b1.check_dimensions(&b2)?;
+        b1.check_dimensions(&b3)?;
+
         let mut result = Vec::new();
         for i in 0..b1.width * b1.height {
             result.push((b1.buffer[i], b2.buffer[i], b3.buffer[i]));
         }
-        Buffer {
+        Ok(Buffer {
             width: b1.width,
             height: b1.height,
             buffer: result,
-        }
+        })
     }
 
     pub fn flatten(&self) -> Vec<u8> {
@@ -283,3 +390,109 @@ impl Buffer<(u8, u8, u8)> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::tests::header;
+    use crate::header::ElementType;
+
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "bread-buffer-{}-{}.bread",
+            std::process::id(),
+            name
+        ))
+    }
+
+    /// Writes a header for `width`x`height` u32 elements followed by `data` bytes and loads it
+    fn load_raw(
+        name: &str,
+        width: usize,
+        height: usize,
+        data: usize,
+    ) -> Result<Buffer<u32>, Error> {
+        let file = temp_file(name);
+        let mut bytes = Vec::new();
+        header()
+            .write(&mut bytes, ElementType::U32, width, height)
+            .unwrap();
+        bytes.resize(bytes.len() + data, 0);
+        fs::write(&file, bytes).unwrap();
+        let loaded = Buffer::load(&file);
+        fs::remove_file(&file).unwrap();
+        loaded.map(|(_, buffer)| buffer)
+    }
+
+    #[test]
+    fn load_checks_the_file() {
+        assert!(load_raw("exact", 3, 2, 24).is_ok());
+        assert!(matches!(
+            load_raw("short", 3, 2, 23),
+            Err(Error::FileSize {
+                expected: 24,
+                found: 23
+            })
+        ));
+        assert!(matches!(
+            load_raw("long", 3, 2, 25),
+            Err(Error::FileSize {
+                expected: 24,
+                found: 25
+            })
+        ));
+        assert!(matches!(
+            load_raw("huge", usize::MAX, 2, 0),
+            Err(Error::InvalidHeader(_))
+        ));
+        // The number of elements fits, their bytes do not
+        assert!(matches!(
+            load_raw("huge-data", usize::MAX / 2, 2, 0),
+            Err(Error::InvalidHeader(_))
+        ));
+        assert!(matches!(
+            Buffer::<u32>::load(temp_file("missing")),
+            Err(Error::Io(_))
+        ));
+    }
+
+    #[test]
+    fn loads_other_element_types() {
+        let file = temp_file("element-types");
+        let counts = Buffer::<u32>::from_vec(2, 1, vec![3, u32::MAX]);
+        counts.store(&header(), &file).unwrap();
+        let (_, wide) = Buffer::<u64>::load(&file).unwrap();
+        assert_eq!(wide.buffer(), &vec![3, u32::MAX as u64]);
+        assert_eq!(ElementType::of_file(&file).unwrap(), ElementType::U32);
+
+        let weights = Buffer::<f64>::from_vec(2, 1, vec![0.25, 1e12]);
+        weights.store(&header(), &file).unwrap();
+        let (_, loaded) = Buffer::<f64>::load(&file).unwrap();
+        let other = Buffer::<u32>::load(&file);
+        fs::remove_file(&file).unwrap();
+        assert_eq!(loaded.buffer(), weights.buffer());
+        assert!(matches!(
+            other,
+            Err(Error::ElementTypeMismatch {
+                expected: ElementType::U32,
+                found: ElementType::F64
+            })
+        ));
+    }
+
+    #[test]
+    fn join_checks_dimensions() {
+        let b = |width, height| Buffer::<u8>::new_default(width, height);
+        assert!(Buffer::join(b(3, 2), b(3, 2), b(3, 2)).is_ok());
+        assert!(matches!(
+            Buffer::join(b(3, 2), b(2, 3), b(3, 2)),
+            Err(Error::DimensionMismatch {
+                expected: (3, 2),
+                found: (2, 3)
+            })
+        ));
+    }
+}
@@ -1,75 +1,651 @@
-use num::complex::Complex32;
+use num::complex::Complex64;
+use serde::{Deserialize, Serialize};
+
+use std::fs;
+use std::path::Path;
 use std::time::Duration;
 
-#[derive(Clone)]
+use crate::accumulator::Accumulation;
+use crate::error::Error;
+use crate::escape::Escape;
+use crate::float::Precision;
+use crate::formula::FormulaConfig;
+use crate::header::ElementType;
+use crate::kernel::{Kernel, MAX_KERNEL_RADIUS};
+use crate::projection::Projection;
+use crate::sampler::{MutateScale, SamplerConfig};
+use crate::tile::Tile;
+
+pub static CONFIG_FILE: &str = "bread.toml";
+/// Pixels smaller than this are only a few hundred f32 steps wide around the Mandelbrot set
+const AUTO_F64_PIXEL_SIZE: f64 = 1e-4;
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct BufferConfig {
     pub min_iterations: u32,
     pub max_iterations: u32,
 }
 
-#[derive(Clone)]
+/// Settings of the Anti-Buddhabrot, which accumulates the orbits that never escape
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AntiConfig {
+    /// Number of iterations after which an orbit counts as bounded
+    pub iterations: u32,
+    /// Number of initial orbit points that are not plotted, before the orbit settles on its
+    /// attractor
+    pub skip: u32,
+    /// Orbits returning this close to an earlier point are treated as periodic, which stops the
+    /// iteration early. 0 disables cycle detection.
+    pub cycle_tolerance: f64,
+}
+impl Default for AntiConfig {
+    fn default() -> AntiConfig {
+        AntiConfig {
+            iterations: 1000,
+            skip: 100,
+            cycle_tolerance: 1e-5,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderConfig {
+    /// Buffer indices used for the red, green and blue channel of the combined image
+    pub channels: [usize; 3],
+    pub exponential: f32,
+
+    pub buffer_exponential: f32,
+    pub buffer_expose: f32,
+}
+impl Default for RenderConfig {
+    fn default() -> RenderConfig {
+        RenderConfig {
+            channels: [2, 1, 0],
+            exponential: 2.0,
+
+            buffer_exponential: 3.0,
+            buffer_expose: 1.3,
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Config {
     pub thread_count: usize,
+    pub precision: Precision,
+    /// Iterate several independent samples at once in lanes the compiler may vectorize. Only has
+    /// an effect for samplers other than mutation and outside of the Anti-Buddhabrot mode. Gives
+    /// the same buffers as iterating one sample after another and is a few percent faster in
+    /// release builds.
+    pub batch: bool,
+    /// Whether every thread accumulates into its own buffers or all threads share them
+    pub accumulation: Accumulation,
+    /// Type of the hit counters in the buffers. Integer counters saturate, u64 does not for any
+    /// practical run. Floating-point counters add the weights of orbits without rounding them.
+    pub counter: ElementType,
+    /// Orbits that return to within this distance of an earlier point are rejected as periodic
+    /// without iterating them to the end. 0 disables the check.
+    pub cycle_tolerance: f64,
+    /// Time after which the generation stops. Also caps the `hits` and `orbits` budgets, but not
+    /// a `samples` budget.
+    #[serde(with = "duration_secs")]
     pub duration: Duration,
     pub cycles: u64,
     pub keep: bool,
+    /// Stop after the samplers proposed this many points instead of after `duration`, which makes
+    /// seeded runs reproducible. Proposals whose orbits are rejected or never reach the view count
+    /// as well, see `orbits` for a budget of the ones that do reach it.
+    pub samples: Option<u64>,
+    /// Stop after this many points of the proposed orbits landed in the view, counting every
+    /// point once whatever its weight and however many buffers it is added to
+    pub hits: Option<u64>,
+    /// Stop after the orbits of this many proposals landed in the view at least once
+    pub orbits: Option<u64>,
+    /// Stop once the relative change of the normalized buffers between two checkpoints drops
+    /// below this value
+    pub convergence: Option<f64>,
+    #[serde(with = "duration_secs")]
+    pub checkpoint_interval: Duration,
+    /// Master seed every worker thread derives its random number generator from. Runs are random
+    /// if not set.
+    pub seed: Option<u64>,
+    /// Generate the view in square tiles of this many pixels one after another, so only one tile
+    /// of every buffer has to fit into memory. Every tile runs the full `samples` orbits and only
+    /// keeps the points landing inside it. With a seed, all tiles see the same orbits. Requires
+    /// the uniform, stratified or Halton sampler, since the weights of the others depend on the
+    /// hits within the tile.
+    pub tile_size: Option<usize>,
 
-    pub min: Complex32,
-    pub max: Complex32,
+    pub min: Complex64,
+    pub max: Complex64,
+    /// Value of z every orbit starts from
+    pub z0: Complex64,
+    /// Plot the orbits of the Julia set of this c instead. The samplers then pick the starting
+    /// value of z from `min..max` and `z0` is ignored.
+    pub julia: Option<Complex64>,
 
     pub width: usize,
     pub height: usize,
 
-    pub buffer_min: Complex32,
-    pub buffer_max: Complex32,
+    pub buffer_min: Complex64,
+    pub buffer_max: Complex64,
 
     pub mutate_jump: f32,
-    pub mutate_deviation: f32,
+    pub mutate_deviation: f64,
+    pub mutate_scale: MutateScale,
+    /// Adapt the deviation of every thread's walk towards this acceptance rate of normal steps
+    pub mutate_acceptance: Option<f32>,
+    /// Splat every proposed orbit weighted by its acceptance probability, and the current one
+    /// by the rejection probability, instead of only the orbit the walk ends up at
+    pub splat_rejected: bool,
+
+    /// Hits per unit of weight that the mutation and importance samplers add to every pixel an
+    /// orbit visits. They give the orbits they prefer less weight, which is rounded to whole hits
+    /// at random, so this should be large enough to keep most of their hits. With the importance
+    /// sampler a uniformly sampled orbit has weight 1, the weights of the mutation walk have an
+    /// unknown global factor, so its buffers only match others after normalizing. The unweighted
+    /// samplers always add one hit per point.
+    pub weight_scale: f32,
+    /// Also plot the conjugate of every orbit, which doubles the orbits per sample. Has no
+    /// effect for formulas whose Buddhabrot is not symmetric about the real axis, or if `min..max`
+    /// is neither symmetric about the real axis nor on one side of it.
+    pub symmetric: bool,
+    /// Radius of the escape test. Defaults to the radius of the formula, beyond which orbits
+    /// never return; with a smaller radius or another `escape` test some escaping orbits are
+    /// cut short.
+    pub bailout: Option<f64>,
+    /// How every orbit point is spread over the pixels around it. Kernels other than nearest
+    /// need a floating-point `counter` and have to reach at least half a pixel, so every point
+    /// reaches the pixel containing it.
+    pub kernel: Kernel,
+    /// Plane of (Re z, Im z, Re c, Im c) the orbits are plotted on, (Re z, Im z) by default
+    pub projection: Projection,
+    /// Projection at the end of an animation. Frames in between are generated by interpolating
+    /// the angles, see [`Projection::interpolate`].
+    pub projection_end: Option<Projection>,
 
+    pub sampler: SamplerConfig,
+    pub formula: FormulaConfig,
+    /// Test deciding that an orbit escaped
+    pub escape: Escape,
+    /// Plot bounded instead of escaping orbits. Each buffer gets the orbit points within its
+    /// iteration band.
+    pub anti: Option<AntiConfig>,
     pub buffers: Vec<BufferConfig>,
+
+    pub render: RenderConfig,
+}
+impl Default for Config {
+    fn default() -> Config {
+        let center = Complex64::new(0.0, 0.0);
+        let size = 4.0;
+        //let center = Complex64::new(-0.158, 1.033);
+        //let size = 0.03;
+        //let center = Complex64::new(-1.015, -0.9005);
+        //let size = 0.025;
+        //let center = Complex64::new(-2100.0/15000.0*4.0, 2400.0/15000.0*4.0);
+        //let size = 0.25;
+
+        Config {
+            thread_count: 6,
+            precision: Precision::default(),
+            batch: false,
+            accumulation: Accumulation::default(),
+            counter: ElementType::default(),
+            cycle_tolerance: 1e-10,
+            duration: Duration::from_secs(10),
+            cycles: 1,
+            keep: false,
+            samples: None,
+            hits: None,
+            orbits: None,
+            convergence: None,
+            checkpoint_interval: Duration::from_secs(10),
+            seed: None,
+            tile_size: None,
+
+            width: 1000,
+            height: 1000,
+
+            min: Complex64::new(-2.0, -2.0),
+            max: Complex64::new(2.0, 2.0),
+            z0: Complex64::new(0.0, 0.0),
+            julia: None,
+
+            buffer_min: center - Complex64::new(size / 2., size / 2.),
+            buffer_max: center + Complex64::new(size / 2., size / 2.),
+
+            mutate_jump: 0.1,
+            mutate_deviation: size * 0.005,
+            mutate_scale: MutateScale::default(),
+            mutate_acceptance: None,
+            splat_rejected: false,
+            weight_scale: 100.0,
+            symmetric: false,
+            bailout: None,
+            kernel: Kernel::default(),
+            projection: Projection::default(),
+            projection_end: None,
+
+            sampler: SamplerConfig::default(),
+            formula: FormulaConfig::default(),
+            escape: Escape::default(),
+            anti: None,
+            buffers: vec![
+                BufferConfig {
+                    min_iterations: 10,
+                    max_iterations: 80,
+                },
+                BufferConfig {
+                    min_iterations: 10,
+                    max_iterations: 400,
+                },
+                BufferConfig {
+                    min_iterations: 10,
+                    max_iterations: 2000,
+                },
+            ],
+
+            render: RenderConfig::default(),
+        }
+    }
 }
+impl Config {
+    /// Reads a config from a TOML file, or a JSON file if the extension is `.json`. Fields that
+    /// are not present in the file keep their default value.
+    pub fn load(file: impl AsRef<Path>) -> Result<Config, Error> {
+        let file = file.as_ref();
+        let content = fs::read_to_string(file)?;
+
+        let config: Config = if file.extension() == Some("json".as_ref()) {
+            serde_json::from_str(&content).map_err(|e| Error::ConfigParse(e.to_string()))?
+        } else {
+            toml::from_str(&content).map_err(|e| Error::ConfigParse(e.to_string()))?
+        };
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    pub fn store(&self, file: impl AsRef<Path>) -> Result<(), Error> {
+        let content = toml::to_string(self).map_err(|e| Error::ConfigParse(e.to_string()))?;
+        fs::write(file, content)?;
+
+        Ok(())
+    }
+
+    /// Centers the view on `center` with a side length of `size` and scales the mutation
+    /// deviation accordingly.
+    pub fn set_view(&mut self, center: Complex64, size: f64) {
+        self.buffer_min = center - Complex64::new(size / 2., size / 2.);
+        self.buffer_max = center + Complex64::new(size / 2., size / 2.);
+        if self.mutate_scale != MutateScale::View {
+            self.mutate_deviation = size * 0.005;
+        }
+    }
+
+    /// Config generating only `tile` of the view
+    pub fn tile(&self, tile: &Tile) -> Config {
+        let (buffer_min, buffer_max) =
+            tile.region(self.buffer_min, self.buffer_max, self.width, self.height);
+        Config {
+            width: tile.width,
+            height: tile.height,
+            buffer_min,
+            buffer_max,
+            tile_size: None,
+            ..self.clone()
+        }
+    }
+
+    /// Resolves [`Precision::Auto`] to the precision the generation runs in
+    pub fn precision(&self) -> Precision {
+        match self.precision {
+            Precision::Auto => {
+                let pixel = ((self.buffer_max.re - self.buffer_min.re) / self.width as f64)
+                    .min((self.buffer_max.im - self.buffer_min.im) / self.height as f64);
+                if pixel < AUTO_F64_PIXEL_SIZE {
+                    Precision::F64
+                } else {
+                    Precision::F32
+                }
+            }
+            precision => precision,
+        }
+    }
+
+    /// Hits per unit of weight the orbits add, `weight_scale` for the weighted samplers and 1 for
+    /// the others, so every point of an unweighted sample adds exactly one hit
+    pub fn weight_scale(&self) -> f32 {
+        if self.sampler.is_weighted() {
+            self.weight_scale
+        } else {
+            1.0
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        fn check(condition: bool, message: &str) -> Result<(), Error> {
+            if condition {
+                Ok(())
+            } else {
+                Err(Error::InvalidConfig(message.to_string()))
+            }
+        }
+
+        check(self.thread_count > 0, "thread_count must be at least 1")?;
+        check(
+            self.cycle_tolerance >= 0.0,
+            "cycle_tolerance must not be negative",
+        )?;
+        check(self.cycles > 0, "cycles must be at least 1")?;
+        check(
+            self.cycles == 1 || self.keep,
+            "more than one cycle requires keep",
+        )?;
+        check(self.samples != Some(0), "samples must be at least 1")?;
+        check(self.hits != Some(0), "hits must be at least 1")?;
+        check(self.orbits != Some(0), "orbits must be at least 1")?;
+        check(
+            self.convergence.is_none_or(|c| c > 0.0),
+            "convergence must be positive",
+        )?;
+        check(self.tile_size != Some(0), "tile_size must be at least 1")?;
+        check(
+            self.tile_size.is_none()
+                || (self.samples.is_some()
+                    && self.hits.is_none()
+                    && self.orbits.is_none()
+                    && self.convergence.is_none()),
+            "tile_size requires samples and cannot be combined with hits, orbits or convergence",
+        )?;
+        check(
+            self.tile_size.is_none() || !self.sampler.is_weighted(),
+            "tile_size requires the uniform, stratified or halton sampler",
+        )?;
+        check(
+            self.checkpoint_interval > Duration::default(),
+            "checkpoint_interval must be positive",
+        )?;
+        check(
+            self.width > 0 && self.height > 0,
+            "width and height must be at least 1",
+        )?;
+        check(
+            self.min.re < self.max.re && self.min.im < self.max.im,
+            "min must be smaller than max",
+        )?;
+        check(
+            self.buffer_min.re < self.buffer_max.re && self.buffer_min.im < self.buffer_max.im,
+            "buffer_min must be smaller than buffer_max",
+        )?;
+        check(
+            (0.0..=1.0).contains(&self.mutate_jump),
+            "mutate_jump must be between 0 and 1",
+        )?;
+        check(
+            self.mutate_deviation > 0.0,
+            "mutate_deviation must be positive",
+        )?;
+        check(
+            self.mutate_acceptance.is_none_or(|a| 0.0 < a && a < 1.0),
+            "mutate_acceptance must be between 0 and 1",
+        )?;
+        check(self.weight_scale > 0.0, "weight_scale must be positive")?;
+        check(
+            (0.5..=MAX_KERNEL_RADIUS).contains(&self.kernel.radius()),
+            "kernel must reach at least 0.5 and at most 7 pixels",
+        )?;
+        check(
+            self.bailout.is_none_or(|r| r > 0.0 && r.is_finite()),
+            "bailout must be positive",
+        )?;
+        if let Escape::Region { min, max } = self.escape {
+            check(
+                min.re < max.re && min.im < max.im,
+                "the min of the escape region must be smaller than its max",
+            )?;
+        }
+        let finite = |p: &Projection| p.angles().iter().all(|(_, _, angle)| angle.is_finite());
+        check(
+            finite(&self.projection) && self.projection_end.as_ref().is_none_or(finite),
+            "projection angles must be finite",
+        )?;
+        check(
+            self.kernel == Kernel::Nearest
+                || matches!(self.counter, ElementType::F32 | ElementType::F64),
+            "kernels other than nearest require counter f32 or f64",
+        )?;
+        match &self.sampler {
+            SamplerConfig::Stratified { strata } => {
+                check(*strata > 0, "sampler.strata must be at least 1")?;
+            }
+            SamplerConfig::Importance {
+                uniform,
+                resolution,
+                cell_samples,
+                ..
+            } => {
+                check(
+                    (0.0..=1.0).contains(uniform),
+                    "sampler.uniform must be between 0 and 1",
+                )?;
+                check(*resolution > 0, "sampler.resolution must be at least 1")?;
+                check(*cell_samples > 0, "sampler.cell_samples must be at least 1")?;
+            }
+            _ => {}
+        }
+        if let FormulaConfig::Multibrot { power } = self.formula {
+            check(
+                power > 1.0 && power.is_finite(),
+                "formula.power must be greater than 1",
+            )?;
+        }
+        if let Some(anti) = &self.anti {
+            check(
+                anti.skip < anti.iterations,
+                "anti.skip must be smaller than anti.iterations",
+            )?;
+            check(
+                anti.cycle_tolerance >= 0.0,
+                "anti.cycle_tolerance must not be negative",
+            )?;
+        }
+        check(!self.buffers.is_empty(), "at least one buffer is required")?;
+        for (i, b) in self.buffers.iter().enumerate() {
+            if b.min_iterations >= b.max_iterations {
+                return Err(Error::InvalidConfig(format!(
+                    "buffer {}: min_iterations must be smaller than max_iterations",
+                    i
+                )));
+            }
+        }
+        check(
+            self.render.channels.iter().all(|c| *c < self.buffers.len()),
+            "render.channels refers to a buffer that does not exist",
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Loads the given config file. Without one, `bread.toml` from the working directory is used if it
+/// exists and the default config otherwise.
+pub fn get_config(file: Option<&Path>) -> Result<Config, Error> {
+    if let Some(file) = file {
+        Config::load(file)
+    } else if Path::new(CONFIG_FILE).exists() {
+        Config::load(CONFIG_FILE)
+    } else {
+        Ok(Config::default())
+    }
+}
+
+/// Stores durations as fractional seconds, which is easier to write by hand than serde's default
+/// representation.
+mod duration_secs {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(duration: &Duration, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_f64(duration.as_secs_f64())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        let secs = f64::deserialize(d)?;
+        if !(secs >= 0.0 && secs.is_finite()) {
+            return Err(serde::de::Error::custom(
+                "duration must be a non-negative number of seconds",
+            ));
+        }
+        Ok(Duration::from_secs_f64(secs))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Writes `content` to a temporary file with `extension` and loads it
+    fn load(content: &str, extension: &str) -> Result<Config, Error> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        let file = std::env::temp_dir().join(format!(
+            "bread-config-{}-{}.{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed),
+            extension
+        ));
+        fs::write(&file, content).unwrap();
+        let config = Config::load(&file);
+        fs::remove_file(&file).unwrap();
+        config
+    }
+
+    #[test]
+    fn missing_fields_keep_defaults() {
+        let config = load("thread_count = 3\nwidth = 640\n", "toml").unwrap();
+        let default = Config::default();
+        assert_eq!(config.thread_count, 3);
+        assert_eq!(config.width, 640);
+        assert_eq!(config.height, default.height);
+        assert_eq!(config.duration, default.duration);
+        assert_eq!(config.buffers.len(), default.buffers.len());
+        assert_eq!(config.render.channels, default.render.channels);
+
+        let config = load(
+            r#"{"duration": 2.5, "render": {"exponential": 1.5}}"#,
+            "json",
+        )
+        .unwrap();
+        assert_eq!(config.duration, Duration::from_millis(2500));
+        assert_eq!(config.render.exponential, 1.5);
+        assert_eq!(config.render.buffer_expose, default.render.buffer_expose);
+    }
+
+    #[test]
+    fn default_is_valid() {
+        assert!(Config::default().validate().is_ok());
+    }
+
+    #[test]
+    fn reports_errors() {
+        assert!(matches!(
+            Config::load("/nonexistent/bread.toml"),
+            Err(Error::Io(_))
+        ));
+        assert!(matches!(
+            load("width = \"wide\"\n", "toml"),
+            Err(Error::ConfigParse(_))
+        ));
+        assert!(matches!(
+            load("widht = 640\n", "toml"),
+            Err(Error::ConfigParse(_))
+        ));
+        assert!(matches!(
+            load("duration = -1.0\n", "toml"),
+            Err(Error::ConfigParse(_))
+        ));
+        assert!(matches!(
+            load("thread_count = 0\n", "toml"),
+            Err(Error::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            load(
+                "[[buffers]]\nmin_iterations = 50\nmax_iterations = 20\n",
+                "toml"
+            ),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+
+    #[test]
+    fn kernels_reach_the_containing_pixel() {
+        let config = |kernel| Config {
+            kernel,
+            counter: ElementType::F32,
+            ..Config::default()
+        };
+        assert!(config(Kernel::Tent { radius: 0.5 }).validate().is_ok());
+        for kernel in [
+            Kernel::Tent { radius: 0.4 },
+            Kernel::Gaussian { sigma: 0.1 },
+        ] {
+            assert!(matches!(
+                config(kernel).validate(),
+                Err(Error::InvalidConfig(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn tiles_require_an_unweighted_sampler() {
+        let mut config = Config {
+            samples: Some(1000),
+            tile_size: Some(64),
+            sampler: SamplerConfig::Mutation,
+            ..Config::default()
+        };
+        assert!(matches!(config.validate(), Err(Error::InvalidConfig(_))));
+        config.sampler = SamplerConfig::Halton;
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn auto_precision_follows_the_pixel_size() {
+        let mut config = Config::default();
+        assert_eq!(config.precision(), Precision::F32);
+        config.set_view(Complex64::new(-0.75, 0.1), 1e-6);
+        assert_eq!(config.precision(), Precision::F64);
+
+        config.precision = Precision::F32;
+        assert_eq!(config.precision(), Precision::F32);
+    }
+
+    #[test]
+    fn store_round_trips() {
+        let mut config = Config {
+            thread_count: 2,
+            duration: Duration::from_millis(1500),
+            ..Config::default()
+        };
+        config.set_view(Complex64::new(-0.5, 0.25), 0.5);
+
+        let file =
+            std::env::temp_dir().join(format!("bread-config-{}-stored.toml", std::process::id()));
+        config.store(&file).unwrap();
+        let loaded = get_config(Some(&file));
+        fs::remove_file(&file).unwrap();
+        let loaded = loaded.unwrap();
 
-pub fn get_config() -> Config {
-    let center = Complex32::new(0.0, 0.0);
-    let size = 4.0;
-    //let center = Complex32::new(-0.158, 1.033);
-    //let size = 0.03;
-    //let center = Complex32::new(-1.015, -0.9005);
-    //let size = 0.025;
-    //let center = Complex32::new(-2100.0/15000.0*4.0, 2400.0/15000.0*4.0);
-    //let size = 0.25;
-
-    Config {
-        thread_count: 6,
-        duration: Duration::from_secs(10),
-        cycles: 1,
-        keep: false,
-
-        width: 1000,
-        height: 1000,
-
-        min: Complex32::new(-2.0, -2.0),
-        max: Complex32::new(2.0, 2.0),
-
-        buffer_min: center - Complex32::new(size / 2., size / 2.),
-        buffer_max: center + Complex32::new(size / 2., size / 2.),
-
-        mutate_jump : 0.1,
-        mutate_deviation: size * 0.005,
-
-        buffers: vec![
-            BufferConfig {
-                min_iterations: 10,
-                max_iterations: 80,
-            },
-            BufferConfig {
-                min_iterations: 10,
-                max_iterations: 400,
-            },
-            BufferConfig {
-                min_iterations: 10,
-                max_iterations: 2000,
-            },
-        ],
+        assert_eq!(loaded.thread_count, 2);
+        assert_eq!(loaded.duration, config.duration);
+        assert_eq!(loaded.buffer_min, Complex64::new(-0.75, 0.0));
+        assert_eq!(loaded.buffer_max, Complex64::new(-0.25, 0.5));
+        assert_eq!(loaded.mutate_deviation, config.mutate_deviation);
     }
 }
@@ -0,0 +1,176 @@
+use rand::Rng;
+
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+
+use crate::header::Element;
+
+/// Hit count of a pixel. Integer counters saturate instead of wrapping around, floating-point
+/// counters also hold the fractional weights of weighted samples.
+pub trait Counter: Element + Copy + Default + PartialOrd + Send + Sync + 'static {
+    /// Counter that several threads can add to at once
+    type Atomic: Send + Sync;
+
+    /// Amount an orbit point of weight `weight` adds. Integer counters round at random so that
+    /// the expected amount is `weight`.
+    fn from_weight(weight: f64, rng: &mut impl Rng) -> Self;
+    fn to_f64(self) -> f64;
+    fn is_zero(self) -> bool;
+    fn add_saturating(self, other: Self) -> Self;
+    fn times(self, n: u32) -> Self;
+    /// Part `fraction` of the amount, rounded to the nearest whole number for integer counters
+    fn scale(self, fraction: f64) -> Self;
+    /// Whether the counter reached its largest value, or for floating-point counters the value
+    /// beyond which a single hit no longer changes it, and stopped counting
+    fn is_saturated(self) -> bool;
+
+    fn new_atomic() -> Self::Atomic;
+    fn add_atomic(atomic: &Self::Atomic, x: Self);
+    /// Returns the value and resets the counter to zero
+    fn take_atomic(atomic: &Self::Atomic) -> Self;
+}
+
+macro_rules! integer_counter {
+    ($t:ty, $atomic:ty) => {
+        impl Counter for $t {
+            type Atomic = $atomic;
+
+            fn from_weight(weight: f64, rng: &mut impl Rng) -> $t {
+                let floor = weight.floor();
+                // Whole numbers do not draw from `rng`, so uniform samplers propose the same
+                // values no matter which orbits hit the view
+                if floor == weight {
+                    return floor as $t;
+                }
+                floor as $t + (rng.gen_range(0.0..1.0) < weight - floor) as $t
+            }
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+            fn is_zero(self) -> bool {
+                self == 0
+            }
+            fn add_saturating(self, other: $t) -> $t {
+                self.saturating_add(other)
+            }
+            fn times(self, n: u32) -> $t {
+                self.saturating_mul(n as $t)
+            }
+            fn scale(self, fraction: f64) -> $t {
+                if fraction == 1.0 {
+                    self
+                } else {
+                    (self as f64 * fraction).round() as $t
+                }
+            }
+            fn is_saturated(self) -> bool {
+                self == <$t>::MAX
+            }
+
+            fn new_atomic() -> $atomic {
+                <$atomic>::new(0)
+            }
+            fn add_atomic(atomic: &$atomic, x: $t) {
+                let _ = atomic.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |y| {
+                    Some(y.saturating_add(x))
+                });
+            }
+            fn take_atomic(atomic: &$atomic) -> $t {
+                atomic.swap(0, Ordering::Relaxed)
+            }
+        }
+    };
+}
+integer_counter!(u32, AtomicU32);
+integer_counter!(u64, AtomicU64);
+
+macro_rules! float_counter {
+    ($t:ty, $atomic:ty) => {
+        impl Counter for $t {
+            /// Bits of the float
+            type Atomic = $atomic;
+
+            fn from_weight(weight: f64, _: &mut impl Rng) -> $t {
+                weight as $t
+            }
+            fn to_f64(self) -> f64 {
+                self as f64
+            }
+            fn is_zero(self) -> bool {
+                self == 0.0
+            }
+            fn add_saturating(self, other: $t) -> $t {
+                self + other
+            }
+            fn times(self, n: u32) -> $t {
+                self * n as $t
+            }
+            fn scale(self, fraction: f64) -> $t {
+                self * fraction as $t
+            }
+            fn is_saturated(self) -> bool {
+                // Beyond 2^24 for f32 and 2^53 for f64, adding a single hit is rounded away
+                self + 1.0 == self
+            }
+
+            fn new_atomic() -> $atomic {
+                <$atomic>::new((0.0 as $t).to_bits())
+            }
+            fn add_atomic(atomic: &$atomic, x: $t) {
+                let _ = atomic.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |y| {
+                    Some((<$t>::from_bits(y) + x).to_bits())
+                });
+            }
+            fn take_atomic(atomic: &$atomic) -> $t {
+                <$t>::from_bits(atomic.swap((0.0 as $t).to_bits(), Ordering::Relaxed))
+            }
+        }
+    };
+}
+float_counter!(f32, AtomicU32);
+float_counter!(f64, AtomicU64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_pcg::Pcg64Mcg;
+
+    #[test]
+    fn integer_weights_keep_the_mean() {
+        let mut rng = Pcg64Mcg::seed_from_u64(2);
+        let total = (0..100_000)
+            .map(|_| u32::from_weight(2.3, &mut rng) as u64)
+            .sum::<u64>();
+        assert!((total as f64 / 100_000.0 - 2.3).abs() < 0.01);
+        assert_eq!(u32::from_weight(4.0, &mut rng), 4);
+        assert_eq!(f32::from_weight(2.3, &mut rng), 2.3);
+    }
+
+    #[test]
+    fn integers_saturate() {
+        assert_eq!(u32::MAX.add_saturating(1), u32::MAX);
+        assert_eq!((u32::MAX / 2).times(3), u32::MAX);
+        assert!(u32::MAX.is_saturated());
+        assert!(!(u32::MAX - 1).is_saturated());
+        assert_eq!(u64::MAX.add_saturating(5), u64::MAX);
+
+        let atomic = u32::new_atomic();
+        u32::add_atomic(&atomic, u32::MAX - 1);
+        u32::add_atomic(&atomic, 3);
+        assert_eq!(u32::take_atomic(&atomic), u32::MAX);
+        assert_eq!(u32::take_atomic(&atomic), 0);
+    }
+
+    #[test]
+    fn floats_add_up() {
+        let atomic = f64::new_atomic();
+        f64::add_atomic(&atomic, 0.25);
+        f64::add_atomic(&atomic, 1.5);
+        assert_eq!(f64::take_atomic(&atomic), 1.75);
+        assert!(f64::take_atomic(&atomic).is_zero());
+        assert!(f32::MAX.add_saturating(f32::MAX).is_saturated());
+        // A single hit no longer counts beyond 2^24
+        assert!(!16_777_215.0f32.is_saturated());
+        assert!(16_777_216.0f32.is_saturated());
+    }
+}
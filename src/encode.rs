@@ -0,0 +1,99 @@
+use image::error::{EncodingError, ImageError};
+use image::{ColorType, ImageFormat};
+use tiff::encoder::{colortype, TiffEncoder};
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use crate::error::Error;
+
+/// Writes a `width`×`height` image strip by strip. `strip` is called with consecutive ranges of
+/// at most `strip_rows` rows and returns their pixels.
+///
+/// 8-bit gray and RGB images in PNG and TIFF format are encoded as the strips arrive, so they
+/// never have to fit into memory. Other formats are collected first and saved with `image`.
+pub fn save_strips(
+    file: impl AsRef<Path>,
+    width: usize,
+    height: usize,
+    color: ColorType,
+    strip_rows: usize,
+    mut strip: impl FnMut(Range<usize>) -> Result<Vec<u8>, Error>,
+) -> Result<(), Error> {
+    let file = file.as_ref();
+    let strips = (0..height)
+        .step_by(strip_rows)
+        .map(|y| y..(y + strip_rows).min(height));
+
+    match (ImageFormat::from_path(file), color) {
+        (Ok(ImageFormat::Png), ColorType::L8 | ColorType::Rgb8) => {
+            let mut encoder = png::Encoder::new(
+                BufWriter::new(File::create(file)?),
+                width as u32,
+                height as u32,
+            );
+            encoder.set_color(if color == ColorType::L8 {
+                png::ColorType::Grayscale
+            } else {
+                png::ColorType::RGB
+            });
+            encoder.set_depth(png::BitDepth::Eight);
+            let mut writer = encoder.write_header().map_err(png_error)?;
+            let mut stream = writer.stream_writer();
+            for rows in strips {
+                stream.write_all(&strip(rows)?)?;
+            }
+            stream.finish().map_err(png_error)?;
+        }
+        (Ok(ImageFormat::Tiff), ColorType::L8) => {
+            write_tiff::<colortype::Gray8>(file, width, height, strip_rows, strips, strip)?;
+        }
+        (Ok(ImageFormat::Tiff), ColorType::Rgb8) => {
+            write_tiff::<colortype::RGB8>(file, width, height, strip_rows, strips, strip)?;
+        }
+        _ => {
+            let mut pixels = Vec::new();
+            for rows in strips {
+                pixels.extend(strip(rows)?);
+            }
+            image::save_buffer(file, &pixels, width as u32, height as u32, color)?;
+        }
+    }
+    Ok(())
+}
+
+fn write_tiff<C: colortype::ColorType<Inner = u8>>(
+    file: &Path,
+    width: usize,
+    height: usize,
+    strip_rows: usize,
+    strips: impl Iterator<Item = Range<usize>>,
+    mut strip: impl FnMut(Range<usize>) -> Result<Vec<u8>, Error>,
+) -> Result<(), Error> {
+    let mut encoder = TiffEncoder::new(BufWriter::new(File::create(file)?)).map_err(tiff_error)?;
+    let mut image = encoder
+        .new_image::<C>(width as u32, height as u32)
+        .map_err(tiff_error)?;
+    image
+        .rows_per_strip(strip_rows as u32)
+        .map_err(tiff_error)?;
+    for rows in strips {
+        image.write_strip(&strip(rows)?).map_err(tiff_error)?;
+    }
+    image.finish().map_err(tiff_error)
+}
+
+fn png_error(e: png::EncodingError) -> Error {
+    Error::Image(ImageError::Encoding(EncodingError::new(
+        ImageFormat::Png.into(),
+        e,
+    )))
+}
+fn tiff_error(e: tiff::TiffError) -> Error {
+    Error::Image(ImageError::Encoding(EncodingError::new(
+        ImageFormat::Tiff.into(),
+        e,
+    )))
+}
@@ -0,0 +1,87 @@
+use std::fmt;
+use std::io;
+
+use crate::header::ElementType;
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+
+    /// The file does not start with the .bread magic number
+    NotABreadFile,
+    UnsupportedVersion(u32),
+    InvalidHeader(String),
+    ElementTypeMismatch {
+        expected: ElementType,
+        found: ElementType,
+    },
+    /// The amount of data in a file does not match the dimensions in its header
+    FileSize {
+        expected: u64,
+        found: u64,
+    },
+    DimensionMismatch {
+        expected: (usize, usize),
+        found: (usize, usize),
+    },
+    /// Two buffers cover different regions or iteration bands, use different samplers, kernels or
+    /// projections or iterate different orbits
+    RegionMismatch,
+
+    ConfigParse(String),
+    InvalidConfig(String),
+
+    Image(image::ImageError),
+}
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(e) => write!(f, "{}", e),
+
+            Error::NotABreadFile => write!(f, "not a .bread file"),
+            Error::UnsupportedVersion(v) => write!(f, "unsupported .bread version {}", v),
+            Error::InvalidHeader(e) => write!(f, "invalid header: {}", e),
+            Error::ElementTypeMismatch { expected, found } => {
+                write!(f, "expected {:?} elements, found {:?}", expected, found)
+            }
+            Error::FileSize { expected, found } => {
+                write!(f, "expected {} bytes of data, found {}", expected, found)
+            }
+            Error::DimensionMismatch { expected, found } => write!(
+                f,
+                "expected dimensions {}x{}, found {}x{}",
+                expected.0, expected.1, found.0, found.1
+            ),
+            Error::RegionMismatch => {
+                write!(
+                    f,
+                    "buffers cover different regions or iteration bands, use different samplers, kernels or projections or iterate different orbits"
+                )
+            }
+
+            Error::ConfigParse(e) => write!(f, "could not parse config: {}", e),
+            Error::InvalidConfig(e) => write!(f, "invalid config: {}", e),
+
+            Error::Image(e) => write!(f, "{}", e),
+        }
+    }
+}
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(e) => Some(e),
+            Error::Image(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
+impl From<image::ImageError> for Error {
+    fn from(e: image::ImageError) -> Error {
+        Error::Image(e)
+    }
+}
@@ -0,0 +1,169 @@
+use num::complex::{Complex, Complex64};
+use serde::{Deserialize, Serialize};
+
+use crate::float::{complex, Float};
+
+/// Orbits always escape beyond this radius, so tests along one axis cannot let them run off to
+/// infinity along the other
+const ESCAPE_LIMIT: f64 = 1e16;
+
+/// Test deciding that an orbit escaped
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Escape {
+    /// $|z|$ exceeds the bailout radius
+    #[default]
+    Circle,
+    /// $|\Re z|$ exceeds the bailout radius
+    Real,
+    /// $|\Im z|$ exceeds the bailout radius
+    Imaginary,
+    /// $|\Re z| + |\Im z|$ exceeds the bailout radius
+    Manhattan,
+    /// $\max(|\Re z|, |\Im z|)$ exceeds the bailout radius
+    Square,
+    /// z leaves the rectangle `min..max`, the bailout radius is not used
+    Region { min: Complex64, max: Complex64 },
+}
+
+/// [`Escape`] with its radius in the precision of the iteration
+#[derive(Clone, Copy, Debug)]
+pub struct Bailout<T> {
+    escape: Escape,
+    radius: T,
+    min: Complex<T>,
+    max: Complex<T>,
+}
+impl<T: Float> Bailout<T> {
+    pub fn new(escape: Escape, radius: T) -> Bailout<T> {
+        let (min, max) = match escape {
+            Escape::Region { min, max } => (complex(min), complex(max)),
+            _ => (Complex::new(-radius, -radius), Complex::new(radius, radius)),
+        };
+        Bailout {
+            escape,
+            radius,
+            min,
+            max,
+        }
+    }
+
+    /// Bailout of the usual circle of radius `radius`
+    pub fn circle(radius: T) -> Bailout<T> {
+        Bailout::new(Escape::Circle, radius)
+    }
+
+    /// Radius of the circle test, which is checked without looking at the kind of test
+    pub fn circle_radius(&self) -> Option<T> {
+        match self.escape {
+            Escape::Circle => Some(self.radius),
+            _ => None,
+        }
+    }
+
+    /// Whether orbits staying within `radius` never escape, so points known to stay inside the
+    /// radius of the formula can be rejected without iterating them
+    pub fn contains_disk(&self, radius: T) -> bool {
+        match self.escape {
+            Escape::Circle | Escape::Real | Escape::Imaginary | Escape::Square => {
+                self.radius >= radius
+            }
+            Escape::Manhattan => self.radius >= radius * T::from_f64(std::f64::consts::SQRT_2),
+            Escape::Region { .. } => {
+                self.min.re <= -radius
+                    && self.min.im <= -radius
+                    && self.max.re >= radius
+                    && self.max.im >= radius
+            }
+        }
+    }
+
+    #[inline(always)]
+    pub fn escaped(&self, z: Complex<T>) -> bool {
+        self.escaped_parts(z.re, z.im)
+    }
+    /// [`Bailout::escaped`] on separate coordinates, without branching on them for the SIMD
+    /// lanes
+    #[inline(always)]
+    pub fn escaped_parts(&self, re: T, im: T) -> bool {
+        let norm_sqr = re * re + im * im;
+        let r = self.radius;
+        let escaped = match self.escape {
+            Escape::Circle => return norm_sqr > r * r,
+            Escape::Real => re.abs() > r,
+            Escape::Imaginary => im.abs() > r,
+            Escape::Manhattan => re.abs() + im.abs() > r,
+            Escape::Square => re.abs().max(im.abs()) > r,
+            Escape::Region { .. } => {
+                (re < self.min.re) | (re > self.max.re) | (im < self.min.im) | (im > self.max.im)
+            }
+        };
+        let limit = T::from_f64(ESCAPE_LIMIT * ESCAPE_LIMIT);
+        escaped | (norm_sqr > limit) | norm_sqr.is_nan()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escaped() {
+        let point = Complex::new(1.5, -1.5);
+        let tests = [
+            (Escape::Circle, true),
+            (Escape::Real, false),
+            (Escape::Imaginary, false),
+            (Escape::Manhattan, true),
+            (Escape::Square, false),
+        ];
+        for (escape, escaped) in tests {
+            assert_eq!(
+                Bailout::new(escape, 2.0).escaped(point),
+                escaped,
+                "{:?}",
+                escape
+            );
+        }
+        let region = Bailout::new(
+            Escape::Region {
+                min: Complex64::new(-1.0, -2.0),
+                max: Complex64::new(2.0, 1.0),
+            },
+            0.0,
+        );
+        assert!(!region.escaped(point));
+        assert!(region.escaped(Complex::new(1.5, 1.5)));
+        assert!(region.escaped(Complex::new(-1.5, 0.0)));
+    }
+
+    #[test]
+    fn escapes_beyond_limit_and_on_nan() {
+        let bailout = Bailout::new(Escape::Real, 2.0);
+        assert!(!bailout.escaped(Complex::new(1.0, 1e10)));
+        assert!(bailout.escaped(Complex::new(1.0, 1e20)));
+        assert!(bailout.escaped(Complex::new(f64::NAN, 0.0)));
+        assert_eq!(Bailout::circle(2.0).circle_radius(), Some(2.0));
+        assert_eq!(bailout.circle_radius(), None);
+    }
+
+    #[test]
+    fn contains_disk() {
+        assert!(Bailout::circle(2.0).contains_disk(2.0));
+        assert!(!Bailout::circle(1.5).contains_disk(2.0));
+        assert!(Bailout::new(Escape::Square, 2.0).contains_disk(2.0));
+        assert!(!Bailout::new(Escape::Manhattan, 2.5).contains_disk(2.0));
+        assert!(Bailout::new(Escape::Manhattan, 3.0).contains_disk(2.0));
+        let region = |min: f64, max: f64| {
+            Bailout::new(
+                Escape::Region {
+                    min: Complex64::new(min, min),
+                    max: Complex64::new(max, max),
+                },
+                0.0,
+            )
+        };
+        assert!(region(-2.0, 3.0).contains_disk(2.0));
+        assert!(!region(-1.0, 3.0).contains_disk(2.0));
+    }
+}
@@ -0,0 +1,52 @@
+use num::complex::{Complex, Complex64};
+use rand::distributions::uniform::SampleUniform;
+use rand::Rng;
+use rand_distr::Normal;
+use serde::{Deserialize, Serialize};
+
+use std::fmt::Debug;
+
+/// Floating point type orbits are iterated in
+pub trait Float: num::Float + SampleUniform + Default + Debug + Send + Sync + 'static {
+    fn from_f64(x: f64) -> Self;
+    fn as_f64(self) -> f64;
+    fn sample_normal(mean: Self, deviation: Self, rng: &mut impl Rng) -> Self;
+}
+impl Float for f32 {
+    fn from_f64(x: f64) -> f32 {
+        x as f32
+    }
+    fn as_f64(self) -> f64 {
+        self as f64
+    }
+    fn sample_normal(mean: f32, deviation: f32, rng: &mut impl Rng) -> f32 {
+        rng.sample(Normal::new(mean, deviation).unwrap())
+    }
+}
+impl Float for f64 {
+    fn from_f64(x: f64) -> f64 {
+        x
+    }
+    fn as_f64(self) -> f64 {
+        self
+    }
+    fn sample_normal(mean: f64, deviation: f64, rng: &mut impl Rng) -> f64 {
+        rng.sample(Normal::new(mean, deviation).unwrap())
+    }
+}
+
+/// Converts a value from the config to the precision of the iteration
+pub fn complex<T: Float>(c: Complex64) -> Complex<T> {
+    Complex::new(T::from_f64(c.re), T::from_f64(c.im))
+}
+
+/// Floating point type the generation runs in
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Precision {
+    /// f64 if the pixels of the view are too small for f32, f32 otherwise
+    #[default]
+    Auto,
+    F32,
+    F64,
+}
@@ -0,0 +1,279 @@
+use num::complex::{Complex, Complex64};
+use serde::{Deserialize, Serialize};
+
+use crate::float::Float;
+use crate::generate::{approximate_is_in_mandelbrot, is_in_period2_bulb};
+use crate::lanes::{Lanes, LANES};
+
+/// An escape-time iteration $z_{n+1} = f(z_n, z_{n-1}, c)$
+pub trait Formula<T: Float>: Sync {
+    fn step(&self, z: Complex<T>, z_previous: Complex<T>, c: Complex<T>) -> Complex<T>;
+
+    /// [`Formula::step`] on every lane. Inlined so the lanes stay in registers.
+    #[inline(always)]
+    fn step_lanes(&self, z: &Lanes<T>, z_previous: &Lanes<T>, c: &Lanes<T>) -> Lanes<T> {
+        let mut next = Lanes::default();
+        for lane in 0..LANES {
+            next.set(
+                lane,
+                self.step(z.get(lane), z_previous.get(lane), c.get(lane)),
+            );
+        }
+        next
+    }
+
+    /// Orbits leaving this radius never return
+    fn bailout(&self) -> T {
+        T::from_f64(2.0)
+    }
+
+    /// Cheap test for values of `c` whose orbits are known to stay bounded, returning the region
+    /// it found `c` in
+    fn interior(&self, _c: Complex<T>) -> Option<Interior> {
+        None
+    }
+
+    /// Whether the orbit of the conjugate of c is the conjugate of the orbit of c, making the
+    /// Buddhabrot symmetric about the real axis
+    fn is_symmetric(&self) -> bool {
+        false
+    }
+
+    /// Whether [`Formula::step`] reads `z_previous`, which then is part of the state the cycle
+    /// detection compares
+    fn uses_previous(&self) -> bool {
+        false
+    }
+}
+
+/// Region of c known to lie inside a set, see [`Formula::interior`]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Interior {
+    /// The main cardioid of the Mandelbrot set
+    Cardioid,
+    /// The period-2 bulb of the Mandelbrot set
+    Bulb,
+}
+
+fn mandelbrot_interior<T: Float>(c: Complex<T>) -> Option<Interior> {
+    if approximate_is_in_mandelbrot(c) {
+        Some(Interior::Cardioid)
+    } else if is_in_period2_bulb(c) {
+        Some(Interior::Bulb)
+    } else {
+        None
+    }
+}
+
+/// $z^2 + c$
+#[derive(Clone, Copy, Debug)]
+pub struct Mandelbrot;
+impl<T: Float> Formula<T> for Mandelbrot {
+    fn step(&self, z: Complex<T>, _: Complex<T>, c: Complex<T>) -> Complex<T> {
+        z * z + c
+    }
+    fn interior(&self, c: Complex<T>) -> Option<Interior> {
+        mandelbrot_interior(c)
+    }
+    fn is_symmetric(&self) -> bool {
+        true
+    }
+}
+
+/// $z^d + c$ for any real $d > 1$
+#[derive(Clone, Copy, Debug)]
+pub struct Multibrot {
+    pub power: f64,
+}
+impl<T: Float> Formula<T> for Multibrot {
+    fn step(&self, z: Complex<T>, _: Complex<T>, c: Complex<T>) -> Complex<T> {
+        if self.power.fract() == 0.0 {
+            z.powi(self.power as i32) + c
+        } else {
+            z.powf(T::from_f64(self.power)) + c
+        }
+    }
+    fn bailout(&self) -> T {
+        // Beyond this radius |z^d| - |c| > |z| for all c in the set
+        T::from_f64(2f64.max(2f64.powf(1.0 / (self.power - 1.0))))
+    }
+    fn interior(&self, c: Complex<T>) -> Option<Interior> {
+        if self.power == 2.0 {
+            mandelbrot_interior(c)
+        } else {
+            None
+        }
+    }
+    fn is_symmetric(&self) -> bool {
+        true
+    }
+}
+
+/// $(|\Re z| + i |\Im z|)^2 + c$
+#[derive(Clone, Copy, Debug)]
+pub struct BurningShip;
+impl<T: Float> Formula<T> for BurningShip {
+    fn step(&self, z: Complex<T>, _: Complex<T>, c: Complex<T>) -> Complex<T> {
+        let z = Complex::new(z.re.abs(), z.im.abs());
+        z * z + c
+    }
+}
+
+/// $\bar{z}^2 + c$, also called Mandelbar
+#[derive(Clone, Copy, Debug)]
+pub struct Tricorn;
+impl<T: Float> Formula<T> for Tricorn {
+    fn step(&self, z: Complex<T>, _: Complex<T>, c: Complex<T>) -> Complex<T> {
+        let z = z.conj();
+        z * z + c
+    }
+    fn is_symmetric(&self) -> bool {
+        true
+    }
+}
+
+/// $|\Re(z^2)| + i \Im(z^2) + c$
+#[derive(Clone, Copy, Debug)]
+pub struct Celtic;
+impl<T: Float> Formula<T> for Celtic {
+    fn step(&self, z: Complex<T>, _: Complex<T>, c: Complex<T>) -> Complex<T> {
+        let z = z * z;
+        Complex::new(z.re.abs(), z.im) + c
+    }
+    fn is_symmetric(&self) -> bool {
+        true
+    }
+}
+
+/// $z_n^2 + c + p z_{n-1}$
+#[derive(Clone, Copy, Debug)]
+pub struct Phoenix<T> {
+    pub p: Complex<T>,
+}
+impl<T: Float> Formula<T> for Phoenix<T> {
+    fn step(&self, z: Complex<T>, z_previous: Complex<T>, c: Complex<T>) -> Complex<T> {
+        z * z + c + self.p * z_previous
+    }
+    fn bailout(&self) -> T {
+        // Beyond this radius |z|^2 - |c| - |p||z_previous| > |z| for all |c| <= 2, as long as the
+        // orbit grows
+        let b = 1.0 + self.p.norm().as_f64();
+        T::from_f64(2f64.max((b + (b * b + 8.0).sqrt()) / 2.0))
+    }
+    fn is_symmetric(&self) -> bool {
+        self.p.im == T::zero()
+    }
+    fn uses_previous(&self) -> bool {
+        true
+    }
+}
+
+/// Selects one of the built-in formulas in the config
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum FormulaConfig {
+    #[default]
+    Mandelbrot,
+    Multibrot {
+        power: f64,
+    },
+    BurningShip,
+    Tricorn,
+    Celtic,
+    Phoenix {
+        p: Complex64,
+    },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::escape::Bailout;
+    use crate::generate::{iterate_step, OrbitEnd};
+    use num::complex::Complex32;
+
+    fn c(re: f32, im: f32) -> Complex32 {
+        Complex32::new(re, im)
+    }
+
+    #[test]
+    fn steps() {
+        let z = c(0.5, -1.5);
+        let z_previous = c(2.0, 1.0);
+        let p = c(0.5, 0.25);
+        let square = z * z;
+
+        assert_eq!(Mandelbrot.step(z, z_previous, p), square + p);
+        assert_eq!(Multibrot { power: 2.0 }.step(z, z_previous, p), square + p);
+        assert_eq!(
+            Multibrot { power: 3.0 }.step(z, z_previous, p),
+            z * z * z + p
+        );
+        assert_eq!(
+            BurningShip.step(z, z_previous, p),
+            c(0.5, 1.5) * c(0.5, 1.5) + p
+        );
+        assert_eq!(Tricorn.step(z, z_previous, p), z.conj() * z.conj() + p);
+        assert_eq!(
+            Celtic.step(z, z_previous, p),
+            c(square.re.abs(), square.im) + p
+        );
+        assert_eq!(
+            Phoenix { p }.step(z, z_previous, c(0.1, 0.0)),
+            square + c(0.1, 0.0) + p * z_previous
+        );
+    }
+
+    fn escape(formula: &impl Formula<f32>, c: Complex32) -> Option<u32> {
+        let end = iterate_step(
+            formula,
+            Complex32::new(0.0, 0.0),
+            c,
+            &Bailout::circle(formula.bailout()),
+            0.0,
+            1000,
+            |_, _| {},
+        );
+        match end {
+            OrbitEnd::Escaped(i) => Some(i),
+            _ => None,
+        }
+    }
+    fn check_escape(formula: &impl Formula<f32>) {
+        assert!(formula.bailout() >= 2.0);
+        assert_eq!(escape(formula, c(0.0, 0.0)), None);
+        assert_eq!(escape(formula, c(-0.25, 0.1)), None);
+        assert!(escape(formula, c(2.5, 0.5)).is_some());
+        assert!(escape(formula, c(-0.5, -2.5)).is_some());
+    }
+
+    #[test]
+    fn escape_tests() {
+        check_escape(&Mandelbrot);
+        check_escape(&Multibrot { power: 3.0 });
+        check_escape(&Multibrot { power: 2.5 });
+        check_escape(&BurningShip);
+        check_escape(&Tricorn);
+        check_escape(&Celtic);
+        check_escape(&Phoenix { p: c(-0.25, 0.0) });
+
+        assert_eq!(Formula::<f32>::bailout(&Multibrot { power: 1.5 }), 4.0);
+        assert!(Phoenix { p: c(1.0, 0.0) }.bailout() > 2.0);
+    }
+
+    #[test]
+    fn only_the_quadratic_mandelbrot_has_known_interior() {
+        let origin = c(0.0, 0.0);
+        let bulb = c(-1.0, 0.1);
+        assert_eq!(Mandelbrot.interior(origin), Some(Interior::Cardioid));
+        assert_eq!(Mandelbrot.interior(bulb), Some(Interior::Bulb));
+        assert_eq!(Mandelbrot.interior(c(-0.75, 0.2)), None);
+        assert_eq!(
+            Multibrot { power: 2.0 }.interior(bulb),
+            Some(Interior::Bulb)
+        );
+        assert_eq!(Multibrot { power: 3.0 }.interior(origin), None);
+        assert_eq!(BurningShip.interior(origin), None);
+        assert_eq!(Phoenix { p: origin }.interior(origin), None);
+    }
+}
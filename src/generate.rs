@@ -0,0 +1,2427 @@
+use crossbeam::thread;
+use num::complex::{Complex, Complex64};
+use rand::{Rng, SeedableRng};
+use rand_pcg::Pcg64Mcg;
+
+use std::ops::Range;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::accumulator::{Accumulator, SharedBuffers};
+use crate::buffer::Buffer;
+use crate::config::{AntiConfig, BufferConfig, Config};
+use crate::counter::Counter;
+use crate::error::Error;
+use crate::escape::{Bailout, Escape};
+use crate::float::{complex, Float, Precision};
+use crate::formula::{
+    BurningShip, Celtic, Formula, FormulaConfig, Interior, Mandelbrot, Multibrot, Phoenix, Tricorn,
+};
+use crate::header::Header;
+use crate::kernel::Kernel;
+use crate::lanes::{LaneOrbits, LANES};
+use crate::projection::{Projection, ProjectionMatrix};
+use crate::sampler::{
+    Halton, Importance, ImportanceMap, MutateScale, Mutation, Sample, Sampler, SamplerConfig,
+    SamplerKind, SamplerStats, Splat, Stratified, Uniform,
+};
+
+static TIMER_CHECK_MS: Duration = Duration::from_millis(50);
+/// Number of samples a worker accumulates before publishing them to the progress counter
+const PROGRESS_BATCH: u64 = 1024;
+
+/// State of a running generation, passed to the progress callback
+#[derive(Clone, Debug)]
+pub struct Progress {
+    pub elapsed: Duration,
+    pub duration: Duration,
+    /// Points proposed over all threads so far
+    pub samples: u64,
+    /// Points of their orbits that landed in the view so far
+    pub hits: u64,
+    /// Proposals whose orbits landed in the view so far
+    pub orbits: u64,
+    /// Number of proposals after which the generation stops, if it is limited by sample count
+    pub target_samples: Option<u64>,
+    /// Number of hits after which the generation stops, if it is limited by hit count
+    pub target_hits: Option<u64>,
+    /// Number of contributing orbits after which the generation stops, if it is limited by them
+    pub target_orbits: Option<u64>,
+    /// Relative change of the buffers at the last checkpoint
+    pub change: Option<f64>,
+}
+impl Progress {
+    /// Fraction of the budget that runs out first. The duration only counts without a sample
+    /// budget, like in [`Generator::run`].
+    pub fn fraction(&self) -> f64 {
+        let time = match self.target_samples {
+            Some(_) => 0.0,
+            None => self.elapsed.as_secs_f64() / self.duration.as_secs_f64(),
+        };
+        let budgets = [
+            (self.samples, self.target_samples),
+            (self.hits, self.target_hits),
+            (self.orbits, self.target_orbits),
+        ];
+        budgets
+            .iter()
+            .filter_map(|(count, target)| target.map(|target| *count as f64 / target as f64))
+            .fold(time, f64::max)
+            .min(1.0)
+    }
+}
+
+type ProgressCallback<'a> = Box<dyn FnMut(&Progress) + 'a>;
+type CheckpointCallback<'a, C> = Box<dyn FnMut(&[HitBuffer<C>]) -> Result<(), Error> + 'a>;
+
+/// Accumulates Buddhabrot orbits for a [`Config`] into buffers of counter type `C`, which has to
+/// match `counter` in the config.
+///
+/// ```no_run
+/// # use bread::{generate::Generator, Config};
+/// let buffers = Generator::<u32>::new(Config::default())
+///     .on_progress(|p| println!("{:.0}%", p.fraction() * 100.0))
+///     .run()
+///     .unwrap();
+/// ```
+pub struct Generator<'a, C: Counter = u32> {
+    config: Config,
+
+    progress: Option<ProgressCallback<'a>>,
+    progress_interval: Duration,
+    checkpoint: Option<CheckpointCallback<'a, C>>,
+    cancel: Arc<AtomicBool>,
+
+    initial: Option<Vec<HitBuffer<C>>>,
+    resume: bool,
+
+    sampler_stats: Vec<SamplerStats>,
+    rejections: Rejections,
+}
+impl<'a, C: Counter> Generator<'a, C> {
+    pub fn new(config: Config) -> Generator<'a, C> {
+        Generator {
+            config,
+
+            progress: None,
+            progress_interval: Duration::from_secs(1),
+            checkpoint: None,
+            cancel: Arc::new(AtomicBool::new(false)),
+
+            initial: None,
+            resume: false,
+
+            sampler_stats: Vec::new(),
+            rejections: Rejections::default(),
+        }
+    }
+
+    /// Calls `f` on the calling thread every `progress_interval` while the workers run
+    pub fn on_progress(mut self, f: impl FnMut(&Progress) + 'a) -> Generator<'a, C> {
+        self.progress = Some(Box::new(f));
+        self
+    }
+    pub fn progress_interval(mut self, interval: Duration) -> Generator<'a, C> {
+        self.progress_interval = interval;
+        self
+    }
+    /// Calls `f` with the merged buffers every `checkpoint_interval`, e.g. to store them so an
+    /// interrupted run can be resumed
+    pub fn on_checkpoint(
+        mut self,
+        f: impl FnMut(&[HitBuffer<C>]) -> Result<(), Error> + 'a,
+    ) -> Generator<'a, C> {
+        self.checkpoint = Some(Box::new(f));
+        self
+    }
+    /// Starts from existing buffers and adds a full run on top of them
+    pub fn accumulate_into(mut self, buffers: Vec<HitBuffer<C>>) -> Generator<'a, C> {
+        self.initial = Some(buffers);
+        self.resume = false;
+        self
+    }
+    /// Continues an interrupted run. The generation time, proposals, hits and contributing orbits
+    /// already in `buffers` count towards the duration and the budgets, so the run stops where the
+    /// original one would have.
+    pub fn resume(mut self, buffers: Vec<HitBuffer<C>>) -> Generator<'a, C> {
+        self.initial = Some(buffers);
+        self.resume = true;
+        self
+    }
+    /// Stops the generation early once `flag` is set. The samples accumulated up to that point
+    /// are still returned.
+    pub fn cancel_flag(mut self, flag: Arc<AtomicBool>) -> Generator<'a, C> {
+        self.cancel = flag;
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+    /// Acceptance statistics of every worker thread of the last run, if its sampler has them
+    pub fn sampler_stats(&self) -> &[SamplerStats] {
+        &self.sampler_stats
+    }
+    /// Candidates rejected by the interior checks over all worker threads of the last run
+    pub fn rejections(&self) -> Rejections {
+        self.rejections
+    }
+
+    /// Runs the generation and returns one buffer per [`BufferConfig`].
+    ///
+    /// The generation stops once the sample, hit or orbit budget is used up, or after the
+    /// configured duration unless there is a sample budget. With `convergence`, the workers pause every
+    /// `checkpoint_interval` and the generation also stops once the buffers stop changing.
+    pub fn run(&mut self) -> Result<Vec<HitBuffer<C>>, Error> {
+        self.config.validate()?;
+        if self.config.counter != C::TYPE {
+            return Err(Error::ElementTypeMismatch {
+                expected: self.config.counter,
+                found: C::TYPE,
+            });
+        }
+        match self.config.precision() {
+            Precision::F64 => self.run_with::<f64>(),
+            _ => self.run_with::<f32>(),
+        }
+    }
+
+    /// Like [`Generator::run`], but iterates in `T` instead of the precision in the config
+    pub fn run_with<T: Float>(&mut self) -> Result<Vec<HitBuffer<C>>, Error> {
+        let mut brot = Brot::<T, C>::new(self.config.clone());
+        brot.prepare()?;
+
+        let mut buffers = brot.new_buffers();
+        let (offset, proposals, hits, orbits) = if let Some(initial) = self.initial.take() {
+            if initial.len() != buffers.len() {
+                return Err(Error::RegionMismatch);
+            }
+            for (buffer, initial) in buffers.iter().zip(&initial) {
+                if !buffer.header.is_compatible(&initial.header) {
+                    return Err(Error::RegionMismatch);
+                }
+                buffer.buffer.check_dimensions(&initial.buffer)?;
+            }
+
+            let offset = initial[0].header.generation_time;
+            let proposals = initial[0].header.proposals;
+            let hits = initial[0].header.hits;
+            let orbits = initial[0].header.orbits;
+            if let Some(seed) = brot.config.seed {
+                // Starting the same streams again would repeat the samples already accumulated
+                brot.config.seed = Some(splitmix64(seed ^ offset.as_nanos() as u64));
+            }
+            buffers = initial;
+
+            if self.resume {
+                (offset, proposals, hits, orbits)
+            } else {
+                (offset, 0, 0, 0)
+            }
+        } else {
+            (Duration::default(), 0, 0, 0)
+        };
+        let config = &brot.config;
+        let remaining = if self.resume {
+            config.duration.saturating_sub(offset)
+        } else {
+            config.duration
+        };
+
+        let start = Instant::now();
+        // Only a sample budget makes runs reproducible, the other budgets are capped by the duration
+        let deadline = if config.samples.is_some() {
+            None
+        } else {
+            Some(start + remaining)
+        };
+        let convergence = config.convergence;
+        let checkpoint_interval = config.checkpoint_interval;
+        let rounds = convergence.is_some() || self.checkpoint.is_some();
+
+        let counters = Counters::default();
+        counters.samples.store(proposals, Ordering::Relaxed);
+        counters.hits.store(hits, Ordering::Relaxed);
+        counters.orbits.store(orbits, Ordering::Relaxed);
+        let sample_budget = config.samples.map(|s| s.saturating_sub(proposals));
+        let hit_budget = config.hits.map(|h| h.saturating_sub(hits));
+        let orbit_budget = config.orbits.map(|o| o.saturating_sub(orbits));
+        let mut workers = (0..config.thread_count)
+            .map(|thread| brot.worker(thread, sample_budget, hit_budget, orbit_budget))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let mut previous: Option<Vec<HitBuffer<C>>> = None;
+        let mut change = None;
+        loop {
+            let end = if rounds {
+                let checkpoint = Instant::now() + checkpoint_interval;
+                Some(deadline.map_or(checkpoint, |deadline| deadline.min(checkpoint)))
+            } else {
+                deadline
+            };
+
+            self.round(&brot, &mut workers, end, &counters, start, offset, change);
+            collect(&brot, &mut workers, &mut buffers)?;
+            let generation_time = offset + start.elapsed();
+            for buffer in &mut buffers {
+                buffer.header.generation_time = generation_time;
+            }
+
+            let mut finished = self.cancel.load(Ordering::Relaxed)
+                || workers.iter().all(|w| w.is_done())
+                || deadline.is_some_and(|deadline| Instant::now() >= deadline);
+            if let Some(convergence) = convergence {
+                if let Some(previous) = &previous {
+                    let c = relative_change(previous, &buffers);
+                    change = Some(c);
+                    finished |= c < convergence;
+                }
+                previous = Some(buffers.clone());
+            }
+            if finished {
+                // Adds the orbits still in the lanes and the ones the samplers held back
+                for worker in &mut workers {
+                    worker.finish = true;
+                }
+                self.round(&brot, &mut workers, None, &counters, start, offset, change);
+                collect(&brot, &mut workers, &mut buffers)?;
+                for buffer in &mut buffers {
+                    buffer.header.generation_time = offset + start.elapsed();
+                }
+                break;
+            }
+            if let Some(checkpoint) = &mut self.checkpoint {
+                checkpoint(&buffers)?;
+            }
+        }
+
+        self.sampler_stats = workers.iter().filter_map(|w| w.sampler.stats()).collect();
+        self.rejections = Rejections::default();
+        for worker in &workers {
+            self.rejections.merge(&worker.rejections);
+        }
+        Ok(buffers)
+    }
+
+    /// Lets all workers accumulate orbits until `end` or until they are done
+    #[allow(clippy::too_many_arguments)]
+    fn round<T: Float>(
+        &mut self,
+        brot: &Brot<T, C>,
+        workers: &mut [Worker<T, C>],
+        end: Option<Instant>,
+        counters: &Counters,
+        start: Instant,
+        offset: Duration,
+        change: Option<f64>,
+    ) {
+        let running = AtomicUsize::new(workers.len());
+
+        let cancel = &*self.cancel;
+        let progress = &mut self.progress;
+        let progress_interval = self.progress_interval;
+        let running = &running;
+
+        thread::scope(|scope| {
+            for worker in workers.iter_mut() {
+                scope.spawn(move |_| {
+                    brot.work(worker, end, counters, cancel);
+                    running.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+
+            if let Some(progress) = progress {
+                let mut last_report = Instant::now();
+                while running.load(Ordering::SeqCst) > 0 {
+                    std::thread::sleep(TIMER_CHECK_MS.min(progress_interval));
+                    if last_report.elapsed() >= progress_interval {
+                        last_report = Instant::now();
+                        progress(&Progress {
+                            elapsed: offset + start.elapsed(),
+                            duration: brot.config.duration,
+                            samples: counters.samples.load(Ordering::Relaxed),
+                            hits: counters.hits.load(Ordering::Relaxed),
+                            orbits: counters.orbits.load(Ordering::Relaxed),
+                            target_samples: brot.config.samples,
+                            target_hits: brot.config.hits,
+                            target_orbits: brot.config.orbits,
+                            change,
+                        });
+                    }
+                }
+            }
+        })
+        .expect("Thread panicked");
+    }
+}
+
+/// Relative L1 distance between the normalized densities of two sets of buffers. Returns the
+/// largest distance over all buffers.
+pub fn relative_change<C: Counter>(old: &[HitBuffer<C>], new: &[HitBuffer<C>]) -> f64 {
+    old.iter()
+        .zip(new)
+        .map(|(old, new)| {
+            let old_sum = old.buffer.buffer().iter().map(|x| x.to_f64()).sum::<f64>();
+            let new_sum = new.buffer.buffer().iter().map(|x| x.to_f64()).sum::<f64>();
+            if old_sum == 0.0 || new_sum == 0.0 {
+                return if old_sum == new_sum {
+                    0.0
+                } else {
+                    f64::INFINITY
+                };
+            }
+
+            old.buffer
+                .buffer()
+                .iter()
+                .zip(new.buffer.buffer())
+                .map(|(a, b)| (a.to_f64() / old_sum - b.to_f64() / new_sum).abs())
+                .sum::<f64>()
+        })
+        .fold(0.0, f64::max)
+}
+
+/// Adds the hits and samples the workers accumulated in the last round to `buffers`
+fn collect<T: Float, C: Counter>(
+    brot: &Brot<T, C>,
+    workers: &mut [Worker<T, C>],
+    buffers: &mut [HitBuffer<C>],
+) -> Result<(), Error> {
+    for worker in workers {
+        for (buffer, header) in buffers.iter_mut().zip(&mut worker.headers) {
+            buffer.header.merge(header);
+            header.samples = 0;
+            header.proposals = 0;
+            header.hits = 0;
+            header.orbits = 0;
+        }
+        worker
+            .accumulator
+            .drain_into(buffers.iter_mut().map(|b| &mut b.buffer))?;
+    }
+    if let Some(shared) = &brot.shared {
+        shared.drain_into(buffers.iter_mut().map(|b| &mut b.buffer))?;
+    }
+    Ok(())
+}
+
+/// Totals over all worker threads, used for progress reports
+#[derive(Default)]
+pub struct Counters {
+    pub samples: AtomicU64,
+    pub hits: AtomicU64,
+    pub orbits: AtomicU64,
+}
+
+/// Number of candidates each interior check rejected before they were iterated to the end
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Rejections {
+    pub cardioid: u64,
+    pub bulb: u64,
+    /// Orbits found to be periodic while iterating
+    pub periodic: u64,
+}
+impl Rejections {
+    pub fn count(&mut self, interior: Interior) {
+        match interior {
+            Interior::Cardioid => self.cardioid += 1,
+            Interior::Bulb => self.bulb += 1,
+        }
+    }
+    pub fn merge(&mut self, other: &Rejections) {
+        self.cardioid += other.cardioid;
+        self.bulb += other.bulb;
+        self.periodic += other.periodic;
+    }
+}
+
+/// State of one worker thread that is kept between checkpoints
+pub struct Worker<T: Float, C: Counter = u32> {
+    pub thread: usize,
+    pub rng: Pcg64Mcg,
+    /// Rounds the weights of orbits to whole hits, separate from `rng` so the samples do not
+    /// depend on how often the weights are rounded
+    pub round_rng: Pcg64Mcg,
+    pub sampler: Box<dyn Sampler<T>>,
+
+    /// Points proposed by this worker since the start of the run, the points of their orbits
+    /// that landed in the view and the proposals with at least one such point
+    pub samples: u64,
+    pub hits: u64,
+    pub contributing: u64,
+    /// This worker's share of the sample, hit and orbit budgets
+    pub sample_budget: Option<u64>,
+    pub hit_budget: Option<u64>,
+    pub orbit_budget: Option<u64>,
+    pub rejections: Rejections,
+    /// Headers of the orbits accumulated since the last checkpoint, one per band
+    pub headers: Vec<Header>,
+    pub accumulator: Accumulator<C>,
+    /// Orbits of a batching sampler that are still being iterated
+    pub orbits: LaneOrbits<T>,
+    /// Set for the last round, in which the worker only finishes the orbits in its lanes and
+    /// flushes its sampler
+    pub finish: bool,
+}
+impl<T: Float, C: Counter> Worker<T, C> {
+    /// Whether the worker has used up its share of one of the budgets
+    pub fn is_done(&self) -> bool {
+        self.sample_budget.is_some_and(|s| self.samples >= s)
+            || self.hit_budget.is_some_and(|h| self.hits >= h)
+            || self.orbit_budget.is_some_and(|o| self.contributing >= o)
+    }
+}
+
+/// The sampling engine shared by all worker threads, iterating in `T` and counting hits in `C`
+pub struct Brot<T: Float, C: Counter = u32> {
+    pub config: Config,
+    pub importance: Option<Arc<ImportanceMap<T>>>,
+
+    /// The view in the precision of the iteration
+    buffer_min: Complex<T>,
+    buffer_max: Complex<T>,
+    /// The view grown by the reach of the kernel, orbit points inside it count as hits
+    reach_min: Complex<T>,
+    reach_max: Complex<T>,
+    z0: Complex<T>,
+    julia: Option<Complex<T>>,
+    projection: ProjectionMatrix<T>,
+    cycle_tolerance: T,
+
+    /// Buffers all workers add to, unless every worker has its own
+    pub shared: Option<Arc<SharedBuffers<C>>>,
+}
+impl<T: Float, C: Counter> Brot<T, C> {
+    pub fn new(config: Config) -> Brot<T, C> {
+        // Points less than the kernel radius from a pixel center spread to it
+        let (min, max) = (config.buffer_min, config.buffer_max);
+        let reach = config.kernel.radius() - 0.5;
+        let reach = Complex64::new(
+            reach * (max.re - min.re) / config.width as f64,
+            reach * (max.im - min.im) / config.height as f64,
+        );
+        Brot {
+            buffer_min: complex(min),
+            buffer_max: complex(max),
+            reach_min: complex(min - reach),
+            reach_max: complex(max + reach),
+            z0: complex(config.z0),
+            julia: config.julia.map(complex),
+            projection: ProjectionMatrix::new(&config.projection),
+            cycle_tolerance: T::from_f64(config.cycle_tolerance),
+            shared: SharedBuffers::new(
+                config.accumulation,
+                config.width,
+                config.height,
+                config.buffers.len(),
+            )
+            .map(Arc::new),
+            config,
+            importance: None,
+        }
+    }
+
+    /// Creates empty headers for all iteration bands of the config
+    pub fn new_headers(&self) -> Vec<Header> {
+        self.config
+            .buffers
+            .iter()
+            .map(|b| Header {
+                seed: self.config.seed,
+                ..config_header(&self.config, b)
+            })
+            .collect()
+    }
+    /// Creates empty buffers for all iteration bands of the config
+    pub fn new_buffers<U: Float>(&self) -> Vec<HitBuffer<C, U>> {
+        self.new_headers()
+            .into_iter()
+            .map(|header| HitBuffer {
+                buffer: Buffer::new_default(self.config.width, self.config.height),
+                min: complex(header.min),
+                max: complex(header.max),
+                header,
+            })
+            .collect()
+    }
+
+    /// Random number generator of a worker thread. With a seed in the config, every thread gets
+    /// its own reproducible stream.
+    pub fn rng(&self, thread: usize) -> Pcg64Mcg {
+        if let Some(seed) = self.config.seed {
+            Pcg64Mcg::seed_from_u64(splitmix64(seed ^ splitmix64(thread as u64)))
+        } else {
+            Pcg64Mcg::from_entropy()
+        }
+    }
+
+    /// Creates the state of a worker thread. `samples`, `hits` and `orbits` are the budgets of the
+    /// whole run, of which the worker takes its share.
+    pub fn worker(
+        &self,
+        thread: usize,
+        samples: Option<u64>,
+        hits: Option<u64>,
+        orbits: Option<u64>,
+    ) -> Result<Worker<T, C>, Error> {
+        let mut rng = self.rng(thread);
+        Ok(Worker {
+            thread,
+            sampler: self.sampler(thread, &mut rng)?,
+            rng,
+            // Streams after the ones of the workers and the importance pass
+            round_rng: self.rng(2 * self.config.thread_count + thread),
+
+            samples: 0,
+            hits: 0,
+            contributing: 0,
+            sample_budget: samples.map(|s| self.thread_share(thread, s)),
+            hit_budget: hits.map(|h| self.thread_share(thread, h)),
+            orbit_budget: orbits.map(|o| self.thread_share(thread, o)),
+            rejections: Rejections::default(),
+            headers: self.new_headers(),
+            accumulator: Accumulator::new(
+                self.shared.as_ref(),
+                self.config.width,
+                self.config.height,
+                self.config.buffers.len(),
+            ),
+            orbits: LaneOrbits::default(),
+            finish: false,
+        })
+    }
+
+    /// Share of `total` that a worker thread takes on
+    fn thread_share(&self, thread: usize, total: u64) -> u64 {
+        let threads = self.config.thread_count as u64;
+        total / threads + ((thread as u64) < total % threads) as u64
+    }
+    /// Start of the share of `total` that a worker thread takes on, after the shares of the
+    /// threads before it
+    fn thread_start(&self, thread: usize, total: u64) -> u64 {
+        (0..thread).map(|t| self.thread_share(t, total)).sum()
+    }
+
+    /// Sampler of a worker thread as selected in the config. The importance sampler needs the
+    /// map loaded by [`Brot::prepare`].
+    pub fn sampler(&self, thread: usize, rng: &mut Pcg64Mcg) -> Result<Box<dyn Sampler<T>>, Error> {
+        let (min, max) = (complex(self.config.min), complex(self.config.max));
+        Ok(match &self.config.sampler {
+            SamplerConfig::Uniform => Box::new(Uniform { min, max }),
+            SamplerConfig::Stratified { strata } => {
+                let cells = *strata as u64 * *strata as u64;
+                Box::new(Stratified {
+                    min,
+                    max,
+                    strata: *strata,
+                    index: self.thread_start(thread, cells),
+                })
+            }
+            SamplerConfig::Halton => Box::new(Halton::new(min, max, rng)),
+            SamplerConfig::Mutation => {
+                let config = &self.config;
+                let deviation = if config.mutate_scale == MutateScale::View {
+                    config.mutate_deviation * (config.buffer_max.re - config.buffer_min.re)
+                } else {
+                    config.mutate_deviation
+                };
+                let deviation = T::from_f64(deviation);
+                let current = rand_complex(min, max, rng);
+                Box::new(Mutation {
+                    escape_time_scaling: config.mutate_scale == MutateScale::EscapeTime,
+                    target_acceptance: config.mutate_acceptance,
+                    splat_rejected: config.splat_rejected,
+                    ..Mutation::new(min, max, config.mutate_jump, deviation, current)
+                })
+            }
+            SamplerConfig::Importance { uniform, .. } => Box::new(Importance {
+                map: self.importance.clone().ok_or_else(|| {
+                    Error::InvalidConfig("the importance map has not been prepared".to_string())
+                })?,
+                uniform: *uniform,
+            }),
+        })
+    }
+
+    /// Loads what the configured sampler needs before workers can be created. The importance map
+    /// is built and stored if its file does not exist yet or does not match the config.
+    pub fn prepare(&mut self) -> Result<(), Error> {
+        if let SamplerConfig::Importance {
+            map, resolution, ..
+        } = &self.config.sampler
+        {
+            let stored = if map.exists() {
+                Some(HitBuffer::<u32, T>::load(map)?)
+            } else {
+                None
+            };
+            let header = self.importance_header();
+            let buffer = match stored {
+                Some(stored)
+                    if stored.header.is_compatible(&header)
+                        && stored.buffer.width() == *resolution
+                        && stored.buffer.height() == *resolution =>
+                {
+                    stored
+                }
+                _ => {
+                    let buffer = self.importance_pass()?;
+                    buffer.store(map)?;
+                    buffer
+                }
+            };
+            self.importance = Some(Arc::new(ImportanceMap::new(
+                &buffer.buffer,
+                buffer.min,
+                buffer.max,
+            )));
+        }
+        Ok(())
+    }
+
+    /// Header of an importance map over the sampled region, recording the view and everything
+    /// else the hits of the orbits depend on
+    fn importance_header(&self) -> Header {
+        let band = BufferConfig {
+            min_iterations: 0,
+            max_iterations: self.max_iterations(),
+        };
+        let config = &self.config;
+        Header {
+            min: config.min,
+            max: config.max,
+            view: Some((config.buffer_min, config.buffer_max)),
+            // The map counts the hits of the pre-pass without weights
+            weight_scale: 1.0,
+            ..config_header(config, &band)
+        }
+    }
+
+    /// Samples a grid over `min..max` and records the in-view hits of the orbits starting in each
+    /// cell
+    pub fn importance_pass(&self) -> Result<HitBuffer<u32, T>, Error> {
+        match self.config.formula {
+            FormulaConfig::Mandelbrot => self.importance_pass_with(&Mandelbrot),
+            FormulaConfig::Multibrot { power } => self.importance_pass_with(&Multibrot { power }),
+            FormulaConfig::BurningShip => self.importance_pass_with(&BurningShip),
+            FormulaConfig::Tricorn => self.importance_pass_with(&Tricorn),
+            FormulaConfig::Celtic => self.importance_pass_with(&Celtic),
+            FormulaConfig::Phoenix { p } => self.importance_pass_with(&Phoenix { p: complex(p) }),
+        }
+    }
+
+    /// Like [`Brot::importance_pass`], but iterates `formula` instead of the formula in the config
+    pub fn importance_pass_with(
+        &self,
+        formula: &impl Formula<T>,
+    ) -> Result<HitBuffer<u32, T>, Error> {
+        let (resolution, cell_samples) = match &self.config.sampler {
+            SamplerConfig::Importance {
+                resolution,
+                cell_samples,
+                ..
+            } => (*resolution, *cell_samples),
+            _ => {
+                return Err(Error::InvalidConfig(
+                    "the sampler is not an importance sampler".to_string(),
+                ))
+            }
+        };
+        let (min, max) = (self.config.min, self.config.max);
+        let cell_size = Complex::new(
+            (max.re - min.re) / resolution as f64,
+            (max.im - min.im) / resolution as f64,
+        );
+        let (origin, size) = (complex::<T>(min), complex::<T>(cell_size));
+        let iterations = self.max_iterations();
+        let threads = self.config.thread_count;
+
+        let mut buffers = thread::scope(|scope| {
+            let handles = (0..threads)
+                .map(|thread| {
+                    scope.spawn(move |_| {
+                        // Streams after the ones of the workers
+                        let mut rng = self.rng(threads + thread);
+                        let mut buffer = HitBuffer::from_buffer(
+                            Buffer::new_default(resolution, resolution),
+                            self.importance_header(),
+                        );
+                        for cell in (thread..resolution * resolution).step_by(threads) {
+                            let cell_min = origin
+                                + Complex::new(
+                                    T::from_f64((cell % resolution) as f64) * size.re,
+                                    T::from_f64((cell / resolution) as f64) * size.im,
+                                );
+                            for _ in 0..cell_samples {
+                                let c = rand_complex(cell_min, cell_min + size, &mut rng);
+                                if let Some((_, hits)) = self.contribution(
+                                    formula,
+                                    c,
+                                    iterations,
+                                    &mut Rejections::default(),
+                                ) {
+                                    buffer.hit(c, hits);
+                                }
+                            }
+                            buffer.header.samples += cell_samples as u64;
+                        }
+                        buffer
+                    })
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|h| h.join().expect("Thread panicked"))
+                .collect::<Vec<_>>()
+        })
+        .expect("Thread panicked");
+
+        let mut result = buffers.pop().expect("No thread");
+        for buffer in buffers {
+            result.merge(buffer)?;
+        }
+        Ok(result)
+    }
+
+    /// Largest iteration count of all buffers
+    fn max_iterations(&self) -> u32 {
+        self.config
+            .buffers
+            .iter()
+            .map(|b| b.max_iterations)
+            .max()
+            .expect("No buffer")
+    }
+
+    /// Accumulates orbits into the worker's buffers until `end` has passed, the worker is done or
+    /// `cancel` is set
+    pub fn work(
+        &self,
+        worker: &mut Worker<T, C>,
+        end: Option<Instant>,
+        counters: &Counters,
+        cancel: &AtomicBool,
+    ) {
+        // Dispatch once here so the iteration is compiled for every formula
+        match self.config.formula {
+            FormulaConfig::Mandelbrot => self.work_with(&Mandelbrot, worker, end, counters, cancel),
+            FormulaConfig::Multibrot { power } => {
+                self.work_with(&Multibrot { power }, worker, end, counters, cancel)
+            }
+            FormulaConfig::BurningShip => {
+                self.work_with(&BurningShip, worker, end, counters, cancel)
+            }
+            FormulaConfig::Tricorn => self.work_with(&Tricorn, worker, end, counters, cancel),
+            FormulaConfig::Celtic => self.work_with(&Celtic, worker, end, counters, cancel),
+            FormulaConfig::Phoenix { p } => {
+                self.work_with(&Phoenix { p: complex(p) }, worker, end, counters, cancel)
+            }
+        }
+    }
+
+    /// Like [`Brot::work`], but iterates `formula` instead of the formula in the config
+    pub fn work_with(
+        &self,
+        formula: &impl Formula<T>,
+        worker: &mut Worker<T, C>,
+        end: Option<Instant>,
+        counters: &Counters,
+        cancel: &AtomicBool,
+    ) {
+        let iterations = self.max_iterations();
+        let (start_samples, start_hits) = (worker.samples, worker.hits);
+        let start_contributing = worker.contributing;
+
+        let now = Instant::now();
+        let mut timer =
+            end.map(|end| Timer::new(now, end.saturating_duration_since(now), TIMER_CHECK_MS));
+        let mut unpublished_samples = 0;
+        let mut unpublished_hits = 0;
+        let mut unpublished_orbits = 0;
+
+        let batch =
+            self.config.batch && self.config.anti.is_none() && worker.sampler.is_independent();
+        let mut splats = Vec::new();
+
+        loop {
+            let timeout = timer.as_mut().is_some_and(|timer| timer.check());
+            if worker.finish || timeout || worker.is_done() || cancel.load(Ordering::Relaxed) {
+                break;
+            }
+
+            let (hits, contributing) = (worker.hits, worker.contributing);
+            let samples = if batch {
+                self.sample_lanes(formula, worker, iterations, &mut splats)
+            } else {
+                self.sample(formula, worker, iterations, &mut splats)
+            };
+            worker.samples += samples;
+            unpublished_samples += samples;
+            unpublished_hits += worker.hits - hits;
+            unpublished_orbits += worker.contributing - contributing;
+
+            self.splat(formula, worker, &mut splats);
+
+            if unpublished_samples >= PROGRESS_BATCH {
+                counters
+                    .samples
+                    .fetch_add(unpublished_samples, Ordering::Relaxed);
+                counters.hits.fetch_add(unpublished_hits, Ordering::Relaxed);
+                counters
+                    .orbits
+                    .fetch_add(unpublished_orbits, Ordering::Relaxed);
+                unpublished_samples = 0;
+                unpublished_hits = 0;
+                unpublished_orbits = 0;
+            }
+        }
+        // Orbits still in the lanes are continued in the next round, so seeded runs do not depend
+        // on when rounds end
+        while worker.finish && worker.orbits.running() > 0 {
+            let (hits, contributing) = (worker.hits, worker.contributing);
+            let samples = self.finish_lanes(formula, worker, iterations, &mut splats);
+            worker.samples += samples;
+            unpublished_samples += samples;
+            unpublished_hits += worker.hits - hits;
+            unpublished_orbits += worker.contributing - contributing;
+        }
+        // Like the lanes, orbits the sampler holds back are kept until the last round, so seeded
+        // runs do not depend on when rounds end
+        if worker.finish {
+            worker.sampler.flush(&mut splats);
+        }
+        self.splat(formula, worker, &mut splats);
+        worker.accumulator.flush();
+        for header in &mut worker.headers {
+            header.proposals += worker.samples - start_samples;
+            header.hits += worker.hits - start_hits;
+            header.orbits += worker.contributing - start_contributing;
+        }
+
+        counters
+            .samples
+            .fetch_add(unpublished_samples, Ordering::Relaxed);
+        counters.hits.fetch_add(unpublished_hits, Ordering::Relaxed);
+        counters
+            .orbits
+            .fetch_add(unpublished_orbits, Ordering::Relaxed);
+    }
+
+    /// Evaluates one proposal of the worker's sampler and returns the number of samples taken.
+    /// The in-view points of its orbit are added to the worker's hits and contributing orbits.
+    fn sample(
+        &self,
+        formula: &impl Formula<T>,
+        worker: &mut Worker<T, C>,
+        iterations: u32,
+        splats: &mut Vec<Splat<T>>,
+    ) -> u64 {
+        let c = worker.sampler.propose(&mut worker.rng);
+        let (c_iterations, c_hits) = self
+            .contribution(formula, c, iterations, &mut worker.rejections)
+            .unwrap_or((0, 0));
+        let sample = Sample {
+            c,
+            iterations: c_iterations,
+            hits: c_hits,
+        };
+        worker.hits += c_hits as u64;
+        worker.contributing += (c_hits > 0) as u64;
+        worker.sampler.update(sample, &mut worker.rng, splats);
+        1
+    }
+
+    /// Starts proposals of an independent sampler in all free lanes of `orbits` and iterates
+    /// until at least one of them ended. Returns the number of samples taken. Only the escape
+    /// iteration and hits are computed here, the orbits are replayed one by one in
+    /// [`Brot::splat`].
+    fn sample_lanes(
+        &self,
+        formula: &impl Formula<T>,
+        worker: &mut Worker<T, C>,
+        iterations: u32,
+        splats: &mut Vec<Splat<T>>,
+    ) -> u64 {
+        let orbits = &mut worker.orbits;
+        let mut samples = 0;
+        for lane in 0..LANES {
+            while !orbits.is_active(lane) {
+                // Orbits still running count towards the sample budget
+                let started = worker.samples + samples + orbits.running() as u64;
+                if worker.sample_budget.is_some_and(|budget| started >= budget) {
+                    break;
+                }
+
+                let c = worker.sampler.propose(&mut worker.rng);
+                if let Some(interior) = self.interior(formula, c) {
+                    worker.rejections.count(interior);
+                    let sample = Sample {
+                        c,
+                        iterations: 0,
+                        hits: 0,
+                    };
+                    worker.sampler.update(sample, &mut worker.rng, splats);
+                    samples += 1;
+                } else {
+                    let (z, c) = self.orbit(c);
+                    orbits.start(lane, z, c);
+                }
+            }
+        }
+        samples + self.finish_lanes(formula, worker, iterations, splats)
+    }
+
+    /// Iterates `orbits` until at least one of them ended and passes the ended ones to the
+    /// worker's sampler. Returns the number of samples taken and adds the in-view points of their
+    /// orbits to the worker's hits and contributing orbits.
+    fn finish_lanes(
+        &self,
+        formula: &impl Formula<T>,
+        worker: &mut Worker<T, C>,
+        iterations: u32,
+        splats: &mut Vec<Splat<T>>,
+    ) -> u64 {
+        let mut samples = 0;
+        let (sampler, rng) = (&mut worker.sampler, &mut worker.rng);
+        let (rejections, total_hits) = (&mut worker.rejections, &mut worker.hits);
+        let contributing = &mut worker.contributing;
+        worker.orbits.advance(
+            formula,
+            &self.bailout(formula),
+            self.cycle_tolerance,
+            iterations,
+            self.reach_min,
+            self.reach_max,
+            &self.projection,
+            self.conjugate(formula),
+            |_, z, c, end, hits| {
+                let (c_iterations, c_hits) = match end {
+                    OrbitEnd::Escaped(i) if hits > 0 => (i, hits),
+                    OrbitEnd::Periodic => {
+                        rejections.periodic += 1;
+                        (0, 0)
+                    }
+                    _ => (0, 0),
+                };
+                let sample = Sample {
+                    c: self.point(z, c),
+                    iterations: c_iterations,
+                    hits: c_hits,
+                };
+                *total_hits += c_hits as u64;
+                *contributing += (c_hits > 0) as u64;
+                sampler.update(sample, rng, splats);
+                samples += 1;
+            },
+        );
+        samples
+    }
+
+    /// Escape iteration and number of in-view hits of the orbit of the sampled point `point`, or
+    /// `None` if it does not contribute to the buffers. Candidates rejected as bounded are counted
+    /// in `rejections`.
+    fn contribution(
+        &self,
+        formula: &impl Formula<T>,
+        point: Complex<T>,
+        iterations: u32,
+        rejections: &mut Rejections,
+    ) -> Option<(u32, u32)> {
+        let (z, c) = self.orbit(point);
+        if let Some(anti) = &self.config.anti {
+            // Points known to be bounded are exactly the ones the Anti-Buddhabrot wants, so they
+            // are not rejected here
+            self.count_bounded_hitting(formula, z, c, anti)
+                .map(|hits| (anti.iterations, hits))
+        } else if let Some(interior) = self.interior(formula, point) {
+            rejections.count(interior);
+            None
+        } else {
+            self.count_hitting(formula, z, c, iterations, rejections)
+        }
+    }
+
+    /// Starting value of z and c of the orbit of a sampled point, which is c, or z in the Julia
+    /// mode
+    fn orbit(&self, point: Complex<T>) -> (Complex<T>, Complex<T>) {
+        match self.julia {
+            Some(c) => (point, c),
+            None => (self.z0, point),
+        }
+    }
+    /// Sampled point of the orbit starting from `z` with `c`, the inverse of [`Brot::orbit`]
+    fn point(&self, z: Complex<T>, c: Complex<T>) -> Complex<T> {
+        if self.julia.is_some() {
+            z
+        } else {
+            c
+        }
+    }
+
+    /// Region of the set the sampled point is known to lie in, see [`Formula::interior`]
+    fn interior(&self, formula: &impl Formula<T>, point: Complex<T>) -> Option<Interior> {
+        // The known regions only hold for orbits of c starting from 0, which stay within the
+        // radius of the formula but may leave a smaller escape region
+        if self.julia.is_none()
+            && self.z0 == Complex::new(T::zero(), T::zero())
+            && self.bailout(formula).contains_disk(formula.bailout())
+        {
+            formula.interior(point)
+        } else {
+            None
+        }
+    }
+
+    /// Escape test of the config, with the radius of the formula unless the config sets one
+    fn bailout(&self, formula: &impl Formula<T>) -> Bailout<T> {
+        let radius = self
+            .config
+            .bailout
+            .map_or_else(|| formula.bailout(), T::from_f64);
+        Bailout::new(self.config.escape, radius)
+    }
+
+    /// Whether orbits are plotted together with their conjugates
+    fn conjugate(&self, formula: &impl Formula<T>) -> bool {
+        // Orbits only mirror each other if the fixed one of the starting value and c is real, the
+        // other one being sampled, and the conjugates of the samples only cover the mirrored
+        // region if the sampled region is symmetric or lies on one side of the real axis
+        let real = |z: Complex<T>| z.im == T::zero();
+        let (min, max) = (self.config.min, self.config.max);
+        let region = min.im == -max.im || min.im >= 0.0 || max.im <= 0.0;
+        self.config.symmetric
+            && formula.is_symmetric()
+            && self.julia.map_or_else(|| real(self.z0), real)
+            && region
+    }
+
+    /// Number of hits the orbit point `z` of `c` counts as, including its conjugate if
+    /// `conjugate` is set
+    fn hits(&self, z: Complex<T>, c: Complex<T>, conjugate: bool) -> u32 {
+        let (min, max) = (self.reach_min, self.reach_max);
+        let inside = |z: Complex<T>, c: Complex<T>| {
+            let p = self.projection.project(z, c);
+            min.re < p.re && p.re < max.re && min.im < p.im && p.im < max.im
+        };
+        inside(z, c) as u32 + (conjugate && inside(z.conj(), c.conj())) as u32
+    }
+
+    /// Pixel of the buffers containing `c`
+    fn pixel(&self, c: Complex<T>) -> Option<(usize, usize)> {
+        let (width, height) = (self.config.width, self.config.height);
+        pixel(c, self.buffer_min, self.buffer_max, width, height)
+    }
+
+    /// Calls `f` with every pixel of the buffers that the orbit point `z` of `c` spreads to under
+    /// the configured projection and kernel and the fraction of the point the pixel gets
+    fn plot(&self, z: Complex<T>, c: Complex<T>, mut f: impl FnMut(usize, usize, f64)) {
+        let z = self.projection.project(z, c);
+        let (width, height) = (self.config.width, self.config.height);
+        if let Kernel::Nearest = self.config.kernel {
+            if let Some((x, y)) = self.pixel(z) {
+                f(x, y, 1.0);
+            }
+            return;
+        }
+        let (min, max) = (self.buffer_min, self.buffer_max);
+        let x = ((z.re - min.re) / (max.re - min.re)).as_f64() * width as f64;
+        let y = ((z.im - min.im) / (max.im - min.im)).as_f64() * height as f64;
+        self.config.kernel.spread(x, y, width, height, f);
+    }
+
+    /// Adds the orbits in `splats` to the worker's buffers. Integer counters round the weights at
+    /// random to whole hits.
+    fn splat(
+        &self,
+        formula: &impl Formula<T>,
+        worker: &mut Worker<T, C>,
+        splats: &mut Vec<Splat<T>>,
+    ) {
+        let bailout = self.bailout(formula);
+        let conjugate = self.conjugate(formula);
+        // A sample whose conjugate is plotted as well stands for two orbits
+        let orbits = 1 + conjugate as u64;
+        let scale = self.config.weight_scale() as f64;
+        for splat in splats.drain(..) {
+            let weight = splat.weight * scale;
+            let amount = C::from_weight(weight, &mut worker.round_rng);
+            let Sample { c, iterations, .. } = splat.sample;
+            let (z_initial, c) = self.orbit(c);
+            let accumulator = &mut worker.accumulator;
+            let bands = self.config.buffers.iter().zip(&mut worker.headers);
+            for (band, (b, header)) in bands.enumerate() {
+                if let Some(anti) = &self.config.anti {
+                    header.samples += splat.samples * orbits;
+                    if amount.is_zero() {
+                        continue;
+                    }
+                    // Every band gets the part of the bounded orbit within its iterations
+                    let plot = b.min_iterations.max(anti.skip)..b.max_iterations;
+                    iterate_bounded(formula, z_initial, c, &bailout, anti, plot, |z, weight| {
+                        let amount = amount.times(weight);
+                        let mut plot = |z, c| {
+                            self.plot(z, c, |x, y, fraction| {
+                                accumulator.add(band, x, y, amount.scale(fraction));
+                            })
+                        };
+                        plot(z, c);
+                        if conjugate {
+                            plot(z.conj(), c.conj());
+                        }
+                    });
+                } else if b.min_iterations <= iterations && iterations < b.max_iterations {
+                    header.samples += splat.samples * orbits;
+                    if amount.is_zero() {
+                        continue;
+                    }
+                    // Only escaping orbits are replayed, so there is no need to look for cycles
+                    let tolerance = T::zero();
+                    let f = |i, z| {
+                        if b.min_iterations <= i && i < b.max_iterations {
+                            let mut plot = |z, c| {
+                                self.plot(z, c, |x, y, fraction| {
+                                    accumulator.add(band, x, y, amount.scale(fraction));
+                                })
+                            };
+                            plot(z, c);
+                            if conjugate {
+                                plot(z.conj(), c.conj());
+                            }
+                        }
+                    };
+                    iterate_step(
+                        formula,
+                        z_initial,
+                        c,
+                        &bailout,
+                        tolerance,
+                        b.max_iterations,
+                        f,
+                    );
+                }
+            }
+        }
+    }
+
+    /// Iterates `c` and returns its escape iteration and the number of orbit points inside the
+    /// view, or `None` if it does not escape or never hits the view. Orbits found to be periodic
+    /// are counted in `rejections`.
+    pub fn count_hitting(
+        &self,
+        formula: &impl Formula<T>,
+        z: Complex<T>,
+        c: Complex<T>,
+        iterations: u32,
+        rejections: &mut Rejections,
+    ) -> Option<(u32, u32)> {
+        let conjugate = self.conjugate(formula);
+        let mut hits = 0;
+        let end = iterate_step(
+            formula,
+            z,
+            c,
+            &self.bailout(formula),
+            self.cycle_tolerance,
+            iterations,
+            |_, z| hits += self.hits(z, c, conjugate),
+        );
+        match end {
+            OrbitEnd::Escaped(i) if hits > 0 => Some((i, hits)),
+            OrbitEnd::Periodic => {
+                rejections.periodic += 1;
+                None
+            }
+            _ => None,
+        }
+    }
+
+    /// Iterates `c` and returns the number of orbit points after the transient prefix that lie
+    /// inside the view, or `None` if the orbit escapes or never hits the view
+    pub fn count_bounded_hitting(
+        &self,
+        formula: &impl Formula<T>,
+        z: Complex<T>,
+        c: Complex<T>,
+        anti: &AntiConfig,
+    ) -> Option<u32> {
+        let conjugate = self.conjugate(formula);
+        let mut hits = 0;
+        let bounded = iterate_bounded(
+            formula,
+            z,
+            c,
+            &self.bailout(formula),
+            anti,
+            anti.skip..anti.iterations,
+            |z, weight| hits += weight * self.hits(z, c, conjugate),
+        );
+        if bounded && hits > 0 {
+            Some(hits)
+        } else {
+            None
+        }
+    }
+}
+
+pub fn rand_complex<T: Float>(min: Complex<T>, max: Complex<T>, rng: &mut impl Rng) -> Complex<T> {
+    Complex::new(rng.gen_range(min.re..max.re), rng.gen_range(min.im..max.im))
+}
+pub fn rand_complex_normal<T: Float>(
+    mean: Complex<T>,
+    deviation: T,
+    rng: &mut impl Rng,
+) -> Complex<T> {
+    Complex::new(
+        T::sample_normal(mean.re, deviation, rng),
+        T::sample_normal(mean.im, deviation, rng),
+    )
+}
+
+/// Whether `c` lies in the main cardioid
+pub fn approximate_is_in_mandelbrot<T: Float>(c: Complex<T>) -> bool {
+    let quarter = T::from_f64(0.25);
+    let q = (c.re - quarter) * (c.re - quarter) + c.im * c.im;
+    q * (q + c.re - quarter) <= quarter * c.im * c.im
+}
+/// Whether `c` lies in the period-2 bulb, the disk of radius 1/4 around -1
+pub fn is_in_period2_bulb<T: Float>(c: Complex<T>) -> bool {
+    let re = c.re + T::one();
+    re * re + c.im * c.im <= T::from_f64(1.0 / 16.0)
+}
+
+/// How the iteration of an orbit ended
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OrbitEnd {
+    /// Passed the escape test in this iteration
+    Escaped(u32),
+    /// Reached the iteration limit
+    Bounded,
+    /// Returned to an earlier point, so it would have reached the iteration limit
+    Periodic,
+}
+
+/// Squared distance of the orbit state `(z, z_previous)` from the saved one of the cycle
+/// detection, which only includes the previous points if the formula reads them
+#[inline(always)]
+fn distance_sqr<T: Float>(
+    z: Complex<T>,
+    z_previous: Complex<T>,
+    saved: Complex<T>,
+    saved_previous: Complex<T>,
+    previous: bool,
+) -> T {
+    let distance = (z - saved).norm_sqr();
+    if previous {
+        distance + (z_previous - saved_previous).norm_sqr()
+    } else {
+        distance
+    }
+}
+
+/// Iterates `z` and calls `f` with every orbit point until it escapes.
+///
+/// With a positive `tolerance`, Brent's cycle detection stops the iteration early once the orbit
+/// returns to within `tolerance` of an earlier point.
+pub fn iterate_step<T: Float>(
+    formula: &impl Formula<T>,
+    mut z: Complex<T>,
+    c: Complex<T>,
+    bailout: &Bailout<T>,
+    tolerance: T,
+    iterations: u32,
+    mut f: impl FnMut(u32, Complex<T>),
+) -> OrbitEnd {
+    let check_cycles = tolerance > T::zero();
+    let tolerance = tolerance * tolerance;
+    let previous = formula.uses_previous();
+    let mut z_previous = Complex::new(T::zero(), T::zero());
+
+    // Compare against a saved point that is moved forward whenever the distance to it reaches
+    // the next power of two. Formulas reading the previous point only repeat if it repeats too.
+    let (mut saved, mut saved_previous) = (z, z_previous);
+    let mut saved_i = 0;
+    let mut power = 1;
+    for i in 0..iterations {
+        (z, z_previous) = (formula.step(z, z_previous, c), z);
+        if bailout.escaped(z) {
+            return OrbitEnd::Escaped(i);
+        }
+        f(i, z);
+
+        if check_cycles {
+            if distance_sqr(z, z_previous, saved, saved_previous, previous) <= tolerance {
+                return OrbitEnd::Periodic;
+            }
+            if i + 1 - saved_i == power {
+                (saved, saved_previous) = (z, z_previous);
+                saved_i = i + 1;
+                power *= 2;
+            }
+        }
+    }
+    OrbitEnd::Bounded
+}
+
+/// Iterates `z` for `anti.iterations` steps and calls `f` with every orbit point whose iteration
+/// lies in `plot`, together with the number of iterations it stands for. Returns whether the
+/// orbit stayed bounded; if it escapes, `f` may already have been called for its first points.
+///
+/// Once the orbit returns to within `anti.cycle_tolerance` of an earlier point, the remaining
+/// iterations only repeat the cycle. They are not computed, instead every point of the cycle is
+/// passed once with the number of times it would have been visited.
+pub fn iterate_bounded<T: Float>(
+    formula: &impl Formula<T>,
+    mut z: Complex<T>,
+    c: Complex<T>,
+    bailout: &Bailout<T>,
+    anti: &AntiConfig,
+    plot: Range<u32>,
+    mut f: impl FnMut(Complex<T>, u32),
+) -> bool {
+    let iterations = anti.iterations;
+    let tolerance = T::from_f64(anti.cycle_tolerance * anti.cycle_tolerance);
+    let previous = formula.uses_previous();
+    let mut z_previous = Complex::new(T::zero(), T::zero());
+
+    // Brent's cycle detection: compare against a saved point that is moved forward whenever the
+    // distance to it reaches the next power of two
+    let (mut saved, mut saved_previous) = (z, z_previous);
+    let mut saved_i = 0;
+    let mut power = 1;
+    for i in 0..iterations {
+        (z, z_previous) = (formula.step(z, z_previous, c), z);
+        if bailout.escaped(z) {
+            return false;
+        }
+        if plot.contains(&i) {
+            f(z, 1);
+        }
+
+        let distance = i + 1 - saved_i;
+        if tolerance > T::zero()
+            && distance_sqr(z, z_previous, saved, saved_previous, previous) <= tolerance
+        {
+            // The next `period` points repeat forever
+            let period = distance;
+            for j in i + 1..(i + 1 + period).min(iterations) {
+                (z, z_previous) = (formula.step(z, z_previous, c), z);
+                let weight =
+                    count_congruent(j, period, plot.start.max(j)..plot.end.min(iterations));
+                if weight > 0 {
+                    f(z, weight);
+                }
+            }
+            return true;
+        }
+        if distance == power {
+            (saved, saved_previous) = (z, z_previous);
+            saved_i = i + 1;
+            power *= 2;
+        }
+    }
+    true
+}
+
+/// Number of values in `range` that are congruent to `start` modulo `period`, counting only
+/// values from `start` on
+fn count_congruent(start: u32, period: u32, range: Range<u32>) -> u32 {
+    let from = range.start.max(start);
+    let first = start + (from - start).div_ceil(period) * period;
+    if first >= range.end {
+        0
+    } else {
+        (range.end - 1 - first) / period + 1
+    }
+}
+
+/// Hit counts of one iteration band together with the metadata stored in its .bread file. Orbit
+/// points are mapped to pixels in `T`.
+#[derive(Clone)]
+pub struct HitBuffer<C: Counter = u32, T: Float = f64> {
+    pub buffer: Buffer<C>,
+    pub header: Header,
+
+    min: Complex<T>,
+    max: Complex<T>,
+}
+impl<C: Counter, T: Float> HitBuffer<C, T> {
+    pub fn new(
+        width: usize,
+        height: usize,
+        min: Complex64,
+        max: Complex64,
+        band: &BufferConfig,
+    ) -> HitBuffer<C, T> {
+        HitBuffer {
+            buffer: Buffer::new_default(width, height),
+            header: band_header(min, max, band),
+            min: complex(min),
+            max: complex(max),
+        }
+    }
+
+    /// Buffer covering the region and iteration band described by `header`
+    pub fn from_buffer(buffer: Buffer<C>, header: Header) -> HitBuffer<C, T> {
+        HitBuffer {
+            buffer,
+            min: complex(header.min),
+            max: complex(header.max),
+            header,
+        }
+    }
+
+    /// Loads a buffer of counter type `C`, or of u32 counts converted to `C`
+    pub fn load(file: impl AsRef<Path>) -> Result<HitBuffer<C, T>, Error> {
+        let (header, buffer) = Buffer::load(file)?;
+        Ok(HitBuffer::from_buffer(buffer, header))
+    }
+    pub fn store(&self, file: impl AsRef<Path>) -> Result<(), Error> {
+        self.buffer.store(&self.header, file)
+    }
+
+    /// Adds the hits of another buffer covering the same region and iteration band
+    pub fn merge<U: Float>(&mut self, other: HitBuffer<C, U>) -> Result<(), Error> {
+        if !self.header.is_compatible(&other.header) {
+            return Err(Error::RegionMismatch);
+        }
+        self.buffer.saturating_add_assign(&other.buffer)?;
+        self.header.merge(&other.header);
+        Ok(())
+    }
+
+    /// Adds `i` to the pixel containing `c` and returns whether `c` lies inside the buffer
+    pub fn hit(&mut self, c: Complex<T>, i: C) -> bool {
+        let (width, height) = (self.buffer.width(), self.buffer.height());
+        if let Some((x, y)) = pixel(c, self.min, self.max, width, height) {
+            self.buffer
+                .set(x, y, self.buffer.get(x, y).add_saturating(i));
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Header of an empty buffer of the iteration band `band`
+pub fn band_header(min: Complex64, max: Complex64, band: &BufferConfig) -> Header {
+    Header {
+        min,
+        max,
+        min_iterations: band.min_iterations,
+        max_iterations: band.max_iterations,
+        samples: 0,
+        proposals: 0,
+        hits: 0,
+        orbits: 0,
+        generation_time: Duration::default(),
+        seed: None,
+        sampler: SamplerKind::Uniform,
+        weight_scale: 1.0,
+        kernel: Kernel::Nearest,
+        projection: Projection::default(),
+        formula: FormulaConfig::Mandelbrot,
+        z0: Complex64::default(),
+        julia: None,
+        anti: None,
+        escape: Escape::Circle,
+        bailout: None,
+        symmetric: false,
+        view: None,
+    }
+}
+
+/// Header of an empty buffer of `band` generated with the settings of `config`
+pub fn config_header(config: &Config, band: &BufferConfig) -> Header {
+    Header {
+        sampler: config.sampler.kind(),
+        weight_scale: config.weight_scale(),
+        kernel: config.kernel,
+        projection: config.projection,
+        formula: config.formula,
+        z0: config.z0,
+        julia: config.julia,
+        anti: config.anti.clone(),
+        escape: config.escape,
+        bailout: config.bailout,
+        symmetric: config.symmetric,
+        ..band_header(config.buffer_min, config.buffer_max, band)
+    }
+}
+
+/// Pixel containing `c` in a buffer of `width` by `height` pixels covering `min..max`
+pub fn pixel<T: Float>(
+    c: Complex<T>,
+    min: Complex<T>,
+    max: Complex<T>,
+    width: usize,
+    height: usize,
+) -> Option<(usize, usize)> {
+    let (width, height) = (T::from_f64(width as f64), T::from_f64(height as f64));
+    let x = (c.re - min.re) / (max.re - min.re) * width;
+    let y = (c.im - min.im) / (max.im - min.im) * height;
+
+    if x < T::zero() || y < T::zero() || x >= width || y >= height {
+        return None;
+    }
+    Some((x.as_f64() as usize, y.as_f64() as usize))
+}
+
+/// Checks whether a duration has passed while keeping calls to `Instant::now` rare
+pub struct Timer {
+    start: Instant,
+    total: Duration,
+
+    last_check: Instant,
+    check_difference: Duration,
+    frequency: u32,
+    current: u32,
+}
+impl Timer {
+    pub fn new(start: Instant, total: Duration, check_difference: Duration) -> Timer {
+        Timer {
+            start,
+            total,
+            last_check: Instant::now(),
+            check_difference,
+            frequency: 1,
+            current: 0,
+        }
+    }
+
+    pub fn check(&mut self) -> bool {
+        self.current += 1;
+        if self.current > self.frequency {
+            self.current = 0;
+            if self.last_check.elapsed() > self.check_difference {
+                self.frequency = 1.max(self.frequency / 2);
+            } else {
+                self.frequency *= 2;
+            }
+            self.last_check = Instant::now();
+
+            self.start.elapsed() > self.total
+        } else {
+            false
+        }
+    }
+}
+
+/// Scrambles a seed so that similar inputs give unrelated outputs
+fn splitmix64(x: u64) -> u64 {
+    let mut z = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    z ^ (z >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accumulator::Accumulation;
+    use crate::header::ElementType;
+    use crate::lanes::tests::Skipping;
+    use num::complex::Complex32;
+
+    use std::fs;
+
+    fn config() -> Config {
+        Config {
+            thread_count: 2,
+            duration: Duration::from_millis(200),
+            width: 32,
+            height: 32,
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn generator_fills_every_band() {
+        let mut reports = 0;
+        let buffers = Generator::<u32>::new(config())
+            .progress_interval(Duration::from_millis(20))
+            .on_progress(|_| reports += 1)
+            .run()
+            .unwrap();
+
+        assert!(reports > 0);
+        assert_eq!(buffers.len(), config().buffers.len());
+        for (buffer, band) in buffers.iter().zip(&config().buffers) {
+            assert_eq!(buffer.header.min_iterations, band.min_iterations);
+            assert_eq!(buffer.header.max_iterations, band.max_iterations);
+            assert!(buffer.header.samples > 0);
+            assert!(buffer.header.generation_time >= config().duration);
+            assert!(buffer.buffer.buffer().iter().any(|x| *x > 0));
+        }
+    }
+
+    #[test]
+    fn cancel_stops_early() {
+        let cancel = Arc::new(AtomicBool::new(true));
+        let start = Instant::now();
+        let buffers = Generator::<u32>::new(Config {
+            duration: Duration::from_secs(60),
+            ..config()
+        })
+        .cancel_flag(cancel)
+        .run()
+        .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert_eq!(buffers.len(), config().buffers.len());
+    }
+
+    #[test]
+    fn merge_rejects_other_bands() {
+        let brot = Brot::<f32>::new(config());
+        let mut buffers = brot.new_buffers::<f64>();
+        let other = buffers[1].clone();
+        assert!(matches!(
+            buffers[0].merge(other),
+            Err(Error::RegionMismatch)
+        ));
+        let same = buffers[1].clone();
+        assert!(buffers[1].merge(same).is_ok());
+    }
+
+    #[test]
+    fn iterate_step_reports_escape() {
+        // 0, 1, 2, 5: escapes in the third step
+        let mut points = vec![];
+        let c = Complex32::new(1.0, 0.0);
+        let escape = iterate_step(
+            &Mandelbrot,
+            Complex32::new(0.0, 0.0),
+            c,
+            &Bailout::circle(2.0),
+            0.0,
+            10,
+            |i, z| points.push((i, z)),
+        );
+        assert_eq!(escape, OrbitEnd::Escaped(2));
+        assert_eq!(
+            points,
+            vec![(0, Complex32::new(1.0, 0.0)), (1, Complex32::new(2.0, 0.0))]
+        );
+
+        let c = Complex32::new(-1.0, 0.0);
+        let z = Complex32::new(0.0, 0.0);
+        let bailout = Bailout::circle(2.0);
+        assert_eq!(
+            iterate_step(&Mandelbrot, z, c, &bailout, 0.0, 50, |_, _| {}),
+            OrbitEnd::Bounded
+        );
+        // 0, -1, 0, -1, ...
+        assert_eq!(
+            iterate_step(&Mandelbrot, z, c, &bailout, 1e-5, 50, |_, _| {}),
+            OrbitEnd::Periodic
+        );
+        assert!(approximate_is_in_mandelbrot(Complex32::new(0.0, 0.0)));
+        assert!(!approximate_is_in_mandelbrot(c));
+    }
+
+    #[test]
+    fn cycles_include_the_previous_point() {
+        // 0, 1, 1, 3: the second 1 follows 1 instead of 0, so the orbit does not repeat
+        let (z, c) = (Complex32::new(0.0, 0.0), Complex32::new(1.0, 0.0));
+        let bailout = Bailout::circle(2.0);
+        assert_eq!(
+            iterate_step(&Skipping, z, c, &bailout, 1e-5, 50, |_, _| {}),
+            OrbitEnd::Escaped(2)
+        );
+        let anti = AntiConfig {
+            iterations: 50,
+            skip: 0,
+            cycle_tolerance: 1e-5,
+        };
+        assert!(!iterate_bounded(
+            &Skipping,
+            z,
+            c,
+            &bailout,
+            &anti,
+            0..50,
+            |_, _| {}
+        ));
+    }
+
+    fn seeded(seed: u64) -> Config {
+        Config {
+            samples: Some(20_000),
+            seed: Some(seed),
+            duration: Duration::from_secs(60),
+            ..config()
+        }
+    }
+    fn hits(buffers: &[HitBuffer]) -> Vec<Vec<u32>> {
+        buffers.iter().map(|b| b.buffer.buffer().clone()).collect()
+    }
+
+    #[test]
+    fn seeded_runs_are_reproducible() {
+        let first = Generator::<u32>::new(seeded(7)).run().unwrap();
+        let second = Generator::<u32>::new(seeded(7)).run().unwrap();
+        let other = Generator::<u32>::new(seeded(8)).run().unwrap();
+
+        assert_eq!(hits(&first), hits(&second));
+        assert_ne!(hits(&first), hits(&other));
+        for (a, b) in first.iter().zip(&second) {
+            assert_eq!(a.header.samples, b.header.samples);
+            assert_eq!(a.header.seed, Some(7));
+        }
+    }
+
+    #[test]
+    fn weight_scale_only_scales_weighted_samplers() {
+        let config = |weight_scale| Config {
+            sampler: SamplerConfig::Uniform,
+            weight_scale,
+            ..seeded(5)
+        };
+        let first = Generator::<u32>::new(config(1.0)).run().unwrap();
+        let second = Generator::<u32>::new(config(100.0)).run().unwrap();
+        assert_eq!(hits(&first), hits(&second));
+        assert_eq!(first[0].header.sampler, SamplerKind::Uniform);
+        assert_eq!(second[0].header.weight_scale, 1.0);
+
+        let weighted = config_header(
+            &Config {
+                weight_scale: 100.0,
+                ..seeded(5)
+            },
+            &seeded(5).buffers[0],
+        );
+        assert_eq!(weighted.sampler, SamplerKind::Mutation);
+        assert_eq!(weighted.weight_scale, 100.0);
+    }
+
+    #[test]
+    fn accumulations_agree() {
+        let per_thread = Generator::<u32>::new(seeded(7)).run().unwrap();
+        for accumulation in [Accumulation::Atomic, Accumulation::Sharded] {
+            let shared = Generator::<u32>::new(Config {
+                accumulation,
+                ..seeded(7)
+            })
+            .run()
+            .unwrap();
+            assert_eq!(hits(&per_thread), hits(&shared), "{:?}", accumulation);
+        }
+    }
+
+    #[test]
+    fn seeded_runs_do_not_depend_on_rounds() {
+        // Mutation walks keep their state and pending weight across rounds as well
+        for sampler in [SamplerConfig::Uniform, SamplerConfig::Mutation] {
+            let config = Config {
+                batch: true,
+                sampler,
+                samples: Some(200_000),
+                ..seeded(7)
+            };
+            let single = Generator::<u32>::new(config.clone()).run().unwrap();
+            let mut rounds = 0;
+            let checkpointed = Generator::<u32>::new(Config {
+                checkpoint_interval: Duration::from_millis(1),
+                ..config
+            })
+            .on_checkpoint(|_| {
+                rounds += 1;
+                Ok(())
+            })
+            .run()
+            .unwrap();
+            assert!(rounds > 1);
+            assert_eq!(hits(&single), hits(&checkpointed));
+        }
+    }
+
+    #[test]
+    fn counters_match_the_config() {
+        let counts = Generator::<u32>::new(seeded(7)).run().unwrap();
+        let config = Config {
+            counter: ElementType::U64,
+            ..seeded(7)
+        };
+        let wide = Generator::<u64>::new(config.clone()).run().unwrap();
+        for (counts, wide) in counts.iter().zip(&wide) {
+            let counts = counts.buffer.buffer().iter().map(|&x| x as u64);
+            assert!(counts.eq(wide.buffer.buffer().iter().copied()));
+        }
+        assert!(matches!(
+            Generator::<u32>::new(config).run(),
+            Err(Error::ElementTypeMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn threads_share_the_samples() {
+        for threads in 1..8 {
+            let brot = Brot::<f32>::new(Config {
+                thread_count: threads,
+                ..seeded(1)
+            });
+            let total: u64 = (0..threads).map(|t| brot.thread_share(t, 20_000)).sum();
+            assert_eq!(total, 20_000);
+            // The shares follow each other without gaps or overlaps
+            for t in 1..threads {
+                assert_eq!(
+                    brot.thread_start(t, 10),
+                    brot.thread_start(t - 1, 10) + brot.thread_share(t - 1, 10)
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn workers_stop_at_their_budget() {
+        let cancel = AtomicBool::new(false);
+        let brot = Brot::<f32>::new(Config {
+            samples: Some(3001),
+            ..seeded(1)
+        });
+        let counters = Counters::default();
+        let mut worker = brot.worker(1, brot.config.samples, None, None).unwrap();
+        brot.work(&mut worker, None, &counters, &cancel);
+        assert_eq!(worker.samples, 1500);
+        assert_eq!(counters.samples.load(Ordering::Relaxed), 1500);
+        assert_eq!(counters.hits.load(Ordering::Relaxed), worker.hits);
+
+        let brot = Brot::<f32>::new(Config {
+            samples: None,
+            hits: Some(100_000),
+            ..seeded(1)
+        });
+        let mut worker = brot.worker(0, None, brot.config.hits, None).unwrap();
+        brot.work(&mut worker, None, &Counters::default(), &cancel);
+        let max_iterations = brot
+            .config
+            .buffers
+            .iter()
+            .map(|b| b.max_iterations)
+            .sum::<u32>();
+        assert!(worker.hits >= 50_000);
+        assert!(worker.hits < 50_000 + max_iterations as u64);
+
+        let brot = Brot::<f32>::new(Config {
+            samples: None,
+            orbits: Some(2000),
+            ..seeded(1)
+        });
+        let counters = Counters::default();
+        let mut worker = brot.worker(0, None, None, brot.config.orbits).unwrap();
+        brot.work(&mut worker, None, &counters, &cancel);
+        assert_eq!(worker.contributing, 1000);
+        assert!(worker.samples > worker.contributing);
+        assert_eq!(counters.orbits.load(Ordering::Relaxed), 1000);
+    }
+
+    #[test]
+    fn duration_caps_all_budgets_but_samples() {
+        let start = Instant::now();
+        let buffers = Generator::<u32>::new(Config {
+            hits: Some(u64::MAX),
+            orbits: Some(u64::MAX),
+            ..config()
+        })
+        .run()
+        .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(buffers[0].header.orbits > 0);
+        assert!(buffers[0].header.orbits <= buffers[0].header.proposals);
+
+        let progress = Progress {
+            elapsed: Duration::from_secs(3),
+            duration: Duration::from_secs(10),
+            samples: 500,
+            hits: 20,
+            orbits: 10,
+            target_samples: None,
+            target_hits: Some(100),
+            target_orbits: Some(50),
+            change: None,
+        };
+        assert_eq!(progress.fraction(), 0.3);
+        let progress = Progress {
+            target_samples: Some(1000),
+            ..progress
+        };
+        assert_eq!(progress.fraction(), 0.5);
+    }
+
+    /// Number of pixels that get hits when hitting the center of every pixel
+    fn resolved_pixels<T: Float>(center: Complex64, size: f64) -> usize {
+        let band = BufferConfig {
+            min_iterations: 0,
+            max_iterations: 10,
+        };
+        let half = Complex64::new(size / 2.0, size / 2.0);
+        let mut buffer = HitBuffer::<u32, T>::new(16, 16, center - half, center + half, &band);
+        for y in 0..16 {
+            for x in 0..16 {
+                let offset = Complex64::new(x as f64 + 0.5, y as f64 + 0.5) * (size / 16.0);
+                buffer.hit(complex(center - half + offset), 1);
+            }
+        }
+        buffer.buffer.buffer().iter().filter(|&&h| h > 0).count()
+    }
+
+    #[test]
+    fn deep_views_need_f64() {
+        let center = Complex64::new(-0.75, 0.1);
+        assert_eq!(resolved_pixels::<f32>(center, 0.1), 256);
+        assert_eq!(resolved_pixels::<f64>(center, 1e-9), 256);
+        assert!(resolved_pixels::<f32>(center, 1e-9) < 16);
+    }
+
+    #[test]
+    fn relative_change_compares_densities() {
+        let brot = Brot::<f32>::new(config());
+        let mut a = brot.new_buffers::<f64>();
+        let mut b = brot.new_buffers::<f64>();
+        assert_eq!(relative_change(&a, &b), 0.0);
+
+        a[0].hit(Complex64::new(0.5, 0.5), 1);
+        assert_eq!(relative_change(&a, &b), f64::INFINITY);
+        // Scaling the hits does not change the density
+        b[0].hit(Complex64::new(0.5, 0.5), 3);
+        assert_eq!(relative_change(&a, &b), 0.0);
+        b[0].hit(Complex64::new(-0.5, 0.5), 3);
+        assert!((relative_change(&a, &b) - 1.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn generation_stops_on_convergence() {
+        let start = Instant::now();
+        Generator::<u32>::new(Config {
+            duration: Duration::from_secs(60),
+            convergence: Some(1.0),
+            checkpoint_interval: Duration::from_millis(100),
+            ..config()
+        })
+        .run()
+        .unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+    }
+
+    #[test]
+    fn hits_count_every_point_once() {
+        let band = BufferConfig {
+            min_iterations: 0,
+            max_iterations: 200,
+        };
+        let buffers = Generator::<u32>::new(Config {
+            sampler: SamplerConfig::Uniform,
+            buffers: vec![band; config().buffers.len()],
+            ..seeded(2)
+        })
+        .run()
+        .unwrap();
+        let sums = hits(&buffers)
+            .iter()
+            .map(|b| b.iter().map(|x| *x as u64).sum::<u64>())
+            .collect::<Vec<_>>();
+        assert!(sums[0] > 0);
+        assert_eq!(sums, vec![buffers[0].header.hits; buffers.len()]);
+    }
+
+    #[test]
+    fn resume_counts_the_existing_hits() {
+        let config = Config {
+            samples: None,
+            hits: Some(100_000),
+            ..seeded(3)
+        };
+        let first = Generator::<u32>::new(config.clone()).run().unwrap();
+        assert!(first[0].header.hits >= 100_000);
+
+        // The budget is used up already
+        let resumed = Generator::<u32>::new(config.clone())
+            .resume(first.clone())
+            .run()
+            .unwrap();
+        assert_eq!(hits(&resumed), hits(&first));
+
+        let resumed = Generator::<u32>::new(Config {
+            hits: Some(200_000),
+            ..config.clone()
+        })
+        .resume(first.clone())
+        .run()
+        .unwrap();
+        let total = resumed[0].header.hits;
+        assert!((200_000..250_000).contains(&total), "{}", total);
+
+        // Accumulating adds a full run
+        let accumulated = Generator::<u32>::new(config)
+            .accumulate_into(first)
+            .run()
+            .unwrap();
+        assert!(accumulated[0].header.hits >= 200_000);
+    }
+
+    #[test]
+    fn resume_counts_the_existing_proposals() {
+        let first = Generator::<u32>::new(seeded(4)).run().unwrap();
+        for buffer in &first {
+            assert_eq!(buffer.header.proposals, 20_000);
+        }
+
+        // The sample budget is used up already
+        let resumed = Generator::<u32>::new(seeded(4))
+            .resume(first.clone())
+            .run()
+            .unwrap();
+        assert_eq!(hits(&resumed), hits(&first));
+        assert_eq!(resumed[0].header.proposals, 20_000);
+
+        let resumed = Generator::<u32>::new(Config {
+            samples: Some(30_000),
+            ..seeded(4)
+        })
+        .resume(first)
+        .run()
+        .unwrap();
+        assert_eq!(resumed[0].header.proposals, 30_000);
+    }
+
+    #[test]
+    fn checkpoints_see_the_merged_buffers() {
+        let mut samples = vec![];
+        let buffers = Generator::<u32>::new(Config {
+            duration: Duration::from_millis(300),
+            checkpoint_interval: Duration::from_millis(50),
+            ..config()
+        })
+        .on_checkpoint(|buffers| {
+            samples.push(buffers[2].header.samples);
+            Ok(())
+        })
+        .run()
+        .unwrap();
+
+        assert!(samples.len() >= 2);
+        assert!(samples.windows(2).all(|w| w[0] <= w[1]));
+        assert!(*samples.last().unwrap() <= buffers[2].header.samples);
+    }
+
+    #[test]
+    fn count_congruent_matches_counting() {
+        for start in 0..20 {
+            for period in 1..8 {
+                for from in 0..30 {
+                    for to in from..30 {
+                        let expected = (from..to)
+                            .filter(|j| *j >= start && (j - start) % period == 0)
+                            .count() as u32;
+                        assert_eq!(
+                            count_congruent(start, period, from..to),
+                            expected,
+                            "start {}, period {}, range {}..{}",
+                            start,
+                            period,
+                            from,
+                            to
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    /// Plotted points of a bounded orbit and their weights
+    fn bounded(c: Complex32, cycle_tolerance: f64) -> Option<Vec<(Complex32, u32)>> {
+        let anti = AntiConfig {
+            iterations: 1000,
+            skip: 100,
+            cycle_tolerance,
+        };
+        let mut points = vec![];
+        let z = Complex32::new(0.0, 0.0);
+        iterate_bounded(
+            &Mandelbrot,
+            z,
+            c,
+            &Bailout::circle(2.0),
+            &anti,
+            100..1000,
+            |z, weight| points.push((z, weight)),
+        )
+        .then_some(points)
+    }
+
+    #[test]
+    fn cycle_detection_keeps_the_weights() {
+        // An attracting fixed point, the period-2 bulb and a period-3 bulb
+        for c in [
+            Complex32::new(-0.1, 0.1),
+            Complex32::new(-1.0, 0.05),
+            Complex32::new(-0.12, 0.75),
+        ] {
+            let full = bounded(c, 0.0).unwrap();
+            let detected = bounded(c, 1e-5).unwrap();
+            assert_eq!(full.len(), 900);
+            assert!(detected.len() < full.len());
+
+            let weight = |points: &[(Complex32, u32)]| points.iter().map(|p| p.1).sum::<u32>();
+            let mean = |points: &[(Complex32, u32)]| {
+                points.iter().map(|(z, w)| z * *w as f32).sum::<Complex32>() / weight(points) as f32
+            };
+            assert_eq!(weight(&detected), 900, "{}", c);
+            assert!((mean(&full) - mean(&detected)).norm() < 1e-3, "{}", c);
+        }
+
+        assert!(bounded(Complex32::new(0.3, 0.0), 1e-5).is_none());
+    }
+
+    #[test]
+    fn anti_plots_every_band() {
+        let buffers = Generator::<u32>::new(Config {
+            anti: Some(AntiConfig {
+                iterations: 300,
+                skip: 20,
+                cycle_tolerance: 1e-5,
+            }),
+            ..seeded(2)
+        })
+        .run()
+        .unwrap();
+        for buffer in &buffers {
+            assert!(buffer.header.samples > 0);
+            assert!(buffer.header.anti.is_some());
+            assert!(buffer.buffer.buffer().iter().any(|x| *x > 0));
+        }
+    }
+
+    #[test]
+    fn symmetric_buffers_are_mirrored() {
+        let config = Config {
+            sampler: SamplerConfig::Uniform,
+            precision: Precision::F64,
+            symmetric: true,
+            ..seeded(3)
+        };
+        let buffers = Generator::<u32>::new(config.clone()).run().unwrap();
+        for buffer in &buffers {
+            let b = &buffer.buffer;
+            let (mut total, mut difference) = (0, 0);
+            for y in 0..b.height() {
+                for x in 0..b.width() {
+                    let mirrored = *b.get(x, b.height() - 1 - y);
+                    total += *b.get(x, y) as i64;
+                    difference += (*b.get(x, y) as i64 - mirrored as i64).abs();
+                }
+            }
+            assert!(total > 0);
+            assert!(difference * 100 <= total, "{} of {}", difference, total);
+        }
+
+        // Formulas without the symmetry are not affected
+        let phoenix = FormulaConfig::Phoenix {
+            p: Complex64::new(-0.5, 0.1),
+        };
+        let plain = Generator::<u32>::new(Config {
+            formula: phoenix,
+            symmetric: false,
+            ..config.clone()
+        })
+        .run()
+        .unwrap();
+        let symmetric = Generator::<u32>::new(Config {
+            formula: phoenix,
+            ..config.clone()
+        })
+        .run()
+        .unwrap();
+        assert_eq!(hits(&plain), hits(&symmetric));
+
+        // Neither are sampled regions whose mirror image is only partly sampled
+        let brot = Brot::<f64>::new(config.clone());
+        assert!(brot.conjugate(&Mandelbrot));
+        let brot = Brot::<f64>::new(Config {
+            min: Complex64::new(-2.0, -0.5),
+            max: Complex64::new(1.0, 1.5),
+            ..config.clone()
+        });
+        assert!(!brot.conjugate(&Mandelbrot));
+
+        // Julia sets sample the starting value, so only their c has to be real
+        let z0 = Complex64::new(0.1, 0.2);
+        let julia = |c| {
+            Brot::<f64>::new(Config {
+                z0,
+                julia: Some(c),
+                ..config.clone()
+            })
+        };
+        assert!(julia(Complex64::new(-0.75, 0.0)).conjugate(&Mandelbrot));
+        assert!(!julia(Complex64::new(-0.75, 0.1)).conjugate(&Mandelbrot));
+        assert!(!Brot::<f64>::new(Config { z0, ..config }).conjugate(&Mandelbrot));
+    }
+
+    #[test]
+    fn small_bailouts_skip_the_interior_checks() {
+        // Inside the cardioid, but the orbit leaves a radius of 0.45 on its way to 0.5
+        let c = Complex::new(0.2499, 0.0);
+        let brot = Brot::<f64>::new(config());
+        assert_eq!(brot.interior(&Mandelbrot, c), Some(Interior::Cardioid));
+
+        let brot = Brot::<f64>::new(Config {
+            bailout: Some(0.45),
+            ..config()
+        });
+        assert_eq!(brot.interior(&Mandelbrot, c), None);
+        let end = iterate_step(
+            &Mandelbrot,
+            Complex::new(0.0, 0.0),
+            c,
+            &brot.bailout(&Mandelbrot),
+            0.0,
+            100,
+            |_, _| {},
+        );
+        assert_eq!(end, OrbitEnd::Escaped(15));
+
+        let brot = Brot::<f64>::new(Config {
+            escape: Escape::Manhattan,
+            bailout: Some(3.0),
+            ..config()
+        });
+        assert_eq!(brot.interior(&Mandelbrot, c), Some(Interior::Cardioid));
+    }
+
+    #[test]
+    fn starting_values_change_the_orbits() {
+        let mut plain = Generator::<u32>::new(seeded(5));
+        let plain_hits = hits(&plain.run().unwrap());
+        assert!(plain.rejections().cardioid > 0);
+
+        // The cardioid test only holds for orbits starting at 0
+        let mut shifted = Generator::<u32>::new(Config {
+            z0: Complex64::new(0.1, 0.0),
+            ..seeded(5)
+        });
+        let shifted_buffers = shifted.run().unwrap();
+        assert_eq!(shifted.rejections().cardioid, 0);
+        assert_ne!(hits(&shifted_buffers), plain_hits);
+        assert_eq!(shifted_buffers[0].header.z0, Complex64::new(0.1, 0.0));
+
+        // The Julia mode samples z and ignores z0
+        let julia = Config {
+            julia: Some(Complex64::new(-0.8, 0.156)),
+            ..seeded(5)
+        };
+        let buffers = Generator::<u32>::new(julia.clone()).run().unwrap();
+        let ignored = Generator::<u32>::new(Config {
+            z0: Complex64::new(0.1, 0.0),
+            ..julia.clone()
+        })
+        .run()
+        .unwrap();
+        assert_eq!(hits(&buffers), hits(&ignored));
+        assert_ne!(hits(&buffers), plain_hits);
+        assert!(buffers
+            .iter()
+            .all(|b| b.header.julia == julia.julia && b.buffer.buffer().iter().any(|x| *x > 0)));
+    }
+
+    #[test]
+    fn every_sampler_fills_the_buffers() {
+        for sampler in [
+            SamplerConfig::Uniform,
+            SamplerConfig::Stratified { strata: 16 },
+            SamplerConfig::Halton,
+            SamplerConfig::Mutation,
+        ] {
+            let buffers = Generator::<u32>::new(Config {
+                sampler: sampler.clone(),
+                ..seeded(4)
+            })
+            .run()
+            .unwrap();
+            assert!(
+                buffers.iter().all(|b| b.header.samples > 0),
+                "{:?}",
+                sampler
+            );
+        }
+    }
+
+    fn importance(map: &Path) -> Config {
+        Config {
+            sampler: SamplerConfig::Importance {
+                map: map.to_path_buf(),
+                uniform: 0.1,
+                resolution: 16,
+                cell_samples: 4,
+            },
+            ..seeded(5)
+        }
+    }
+
+    #[test]
+    fn importance_map_is_built_and_reused() {
+        let map =
+            std::env::temp_dir().join(format!("bread-importance-{}.bread", std::process::id()));
+        let mut brot = Brot::<f32>::new(importance(&map));
+        brot.prepare().unwrap();
+        let built = HitBuffer::<u32, f32>::load(&map).unwrap();
+        assert_eq!(built.header.samples, 16 * 16 * 4);
+
+        // The interior of the cardioid never contributes, its boundary does
+        let cell = |re: f32, im: f32| {
+            *built
+                .buffer
+                .get(((re + 2.0) * 4.0) as usize, ((im + 2.0) * 4.0) as usize)
+        };
+        assert_eq!(cell(-0.1, 0.1), 0);
+        assert!(cell(-0.8, 0.3) > 0);
+
+        // A stored map of the same region is used as is
+        let mut marked = built.clone();
+        marked.header.samples = 1;
+        marked.store(&map).unwrap();
+        brot.prepare().unwrap();
+        assert_eq!(HitBuffer::<u32, f32>::load(&map).unwrap().header.samples, 1);
+
+        // So does another view, whose hits the map counts
+        let mut brot = Brot::<f32>::new(Config {
+            buffer_min: Complex64::new(-1.0, -1.0),
+            ..importance(&map)
+        });
+        brot.prepare().unwrap();
+        let rebuilt = HitBuffer::<u32, f32>::load(&map).unwrap();
+        assert_eq!(rebuilt.header.samples, 16 * 16 * 4);
+
+        // Another region needs a new one
+        let mut brot = Brot::<f32>::new(Config {
+            min: Complex64::new(-2.0, -1.5),
+            ..importance(&map)
+        });
+        brot.prepare().unwrap();
+        let rebuilt = HitBuffer::<u32, f32>::load(&map);
+        fs::remove_file(&map).unwrap();
+        let rebuilt = rebuilt.unwrap();
+        assert_eq!(rebuilt.header.samples, 16 * 16 * 4);
+        assert_eq!(rebuilt.header.min, Complex64::new(-2.0, -1.5));
+
+        let buffers = Generator::<u32>::new(importance(&map)).run();
+        fs::remove_file(&map).unwrap();
+        assert!(buffers.unwrap().iter().all(|b| b.header.samples > 0));
+    }
+
+    #[test]
+    fn importance_needs_a_prepared_map() {
+        let map = std::env::temp_dir().join("bread-unprepared.bread");
+        let brot = Brot::<f32>::new(importance(&map));
+        let mut rng = brot.rng(0);
+        assert!(matches!(
+            brot.sampler(0, &mut rng),
+            Err(Error::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            Brot::<f32>::new(config()).importance_pass(),
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+}
@@ -1,331 +1,425 @@
-#![feature(destructuring_assignment)]
-
-use crossbeam::thread;
-use num::complex::Complex32;
-use rand::rngs::SmallRng;
-use rand::{Rng, SeedableRng};
-use rand_distr::Normal;
-use std::path::Path;
+use clap::Parser;
+use num::complex::Complex64;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Duration;
-use std::time::Instant;
 
+use bread::generate::{config_header, Generator, HitBuffer, Progress, Rejections};
 use bread::*;
 
-static TIMER_CHECK_MS: Duration = Duration::from_millis(50);
-
-fn main() {
-    let config = get_config();
-    if config.cycles > 1 && !config.keep {
-        panic!("More than one cycle and not keeping");
-    } else if config.cycles == 1 {
-        println!("Running");
-        run();
-    } else {
-        for i in 0..config.cycles {
-            println!("Cycle {}", i);
-            run();
-        }
-    }
-}
-fn run() {
-    let mut brot = Brot {
-        config: get_config(),
-        results: None,
-    };
-    brot.run();
-    brot.print_stats1();
-    println!("Storing");
-    brot.store();
-    brot.print_stats2();
-}
+/// Accumulates Buddhabrot orbits into .bread buffers
+#[derive(Parser)]
+#[clap(name = "generate", version)]
+struct Args {
+    /// Config file, defaults to bread.toml if it exists
+    #[clap(short, long)]
+    config: Option<PathBuf>,
+    /// Directory the buffers are written to
+    #[clap(short, long, default_value = ".")]
+    output: PathBuf,
+    /// File name prefix of the buffers
+    #[clap(short, long, default_value = "buffer")]
+    prefix: String,
 
-#[derive(Clone)]
-struct Brot {
-    config: Config,
+    /// Generation time per cycle in seconds
+    #[clap(short, long)]
+    duration: Option<f64>,
+    /// Number of times the generation is repeated, requires --keep
+    #[clap(long)]
+    cycles: Option<u64>,
+    /// Add to existing buffers instead of overwriting them
+    #[clap(short, long)]
+    keep: bool,
+    /// Continue an interrupted run from its last checkpoint, using its stored config unless
+    /// --config is given
+    #[clap(short, long, conflicts_with = "keep")]
+    resume: bool,
+    /// Number of worker threads
+    #[clap(short, long)]
+    threads: Option<usize>,
+    /// Stop after this many proposed points instead of after the duration
+    #[clap(short, long)]
+    samples: Option<u64>,
+    /// Stop after this many orbit points landed in the view
+    #[clap(long)]
+    hits: Option<u64>,
+    /// Stop after the orbits of this many proposed points landed in the view
+    #[clap(long)]
+    orbits: Option<u64>,
+    /// Stop once the buffers change less than this between checkpoints
+    #[clap(long)]
+    convergence: Option<f64>,
+    /// Seed for reproducible runs
+    #[clap(long)]
+    seed: Option<u64>,
 
-    results: Option<Vec<HitBuffer>>,
+    /// Center of the view, formatted as `re,im`
+    #[clap(long, allow_hyphen_values = true, parse(try_from_str = parse_complex))]
+    center: Option<Complex64>,
+    /// Side length of the view
+    #[clap(long)]
+    size: Option<f64>,
+    /// Plot the Julia set of this c, formatted as `re,im`
+    #[clap(long, allow_hyphen_values = true, parse(try_from_str = parse_complex))]
+    julia: Option<Complex64>,
+    /// Generate the animation frame a fraction `t` of the way from `projection` to
+    /// `projection_end`
+    #[clap(long)]
+    frame: Option<f64>,
 }
-impl Brot {
-    fn run(&mut self) {
-        let iterations = self
-            .config
-            .buffers
-            .iter()
-            .map(|b| b.max_iterations)
-            .max()
-            .expect("No buffer");
-
-        self.results = Some(
-            thread::scope(|scope| {
-                let mut threads = vec![];
-
-                for _ in 0..self.config.thread_count {
-                    threads.push(scope.spawn(|_| {
-                        let mut rng = SmallRng::from_entropy();
+impl Args {
+    fn config(&self) -> Result<Config, Error> {
+        let stored = self.output.join(format!("{}.toml", self.prefix));
+        let mut config = if self.resume && self.config.is_none() {
+            Config::load(stored)?
+        } else {
+            get_config(self.config.as_deref())?
+        };
 
-                        let mut buffers = vec![
-                            HitBuffer::new(
-                                self.config.width,
-                                self.config.height,
-                                self.config.buffer_min,
-                                self.config.buffer_max
-                            );
-                            self.config.buffers.len()
-                        ];
-                        let mut timer =
-                            Timer::new(Instant::now(), self.config.duration, TIMER_CHECK_MS);
+        if let Some(duration) = self.duration {
+            config.duration = Duration::from_secs_f64(duration);
+        }
+        if let Some(cycles) = self.cycles {
+            config.cycles = cycles;
+        }
+        config.keep |= self.keep;
+        if let Some(threads) = self.threads {
+            config.thread_count = threads;
+        }
+        if self.samples.is_some() {
+            config.samples = self.samples;
+        }
+        if self.hits.is_some() {
+            config.hits = self.hits;
+        }
+        if self.orbits.is_some() {
+            config.orbits = self.orbits;
+        }
+        if self.convergence.is_some() {
+            config.convergence = self.convergence;
+        }
+        if self.seed.is_some() {
+            config.seed = self.seed;
+        }
+        if self.julia.is_some() {
+            config.julia = self.julia;
+        }
+        if let Some(t) = self.frame {
+            let end = config.projection_end.ok_or_else(|| {
+                Error::InvalidConfig("--frame requires projection_end".to_string())
+            })?;
+            // The stored config describes this frame only
+            config.projection = config.projection.interpolate(&end, t);
+            config.projection_end = None;
+        }
+        if self.center.is_some() || self.size.is_some() {
+            let center = self
+                .center
+                .unwrap_or_else(|| (config.buffer_min + config.buffer_max) / 2.0);
+            let size = self
+                .size
+                .unwrap_or(config.buffer_max.re - config.buffer_min.re);
+            config.set_view(center, size);
+        }
 
-                        let z_initial = Complex32::new(0.0, 0.0);
+        config.validate()?;
+        if self.resume && config.tile_size.is_some() {
+            return Err(Error::InvalidConfig(
+                "tiled runs cannot be resumed".to_string(),
+            ));
+        }
+        Ok(config)
+    }
+}
 
-                        let mut c = Self::rand_complex(self.config.min, self.config.max, &mut rng);
-                        let mut c_iterations = 0;
-                        while !timer.check() {
-                            let new_c = self.mutate(c, &mut rng);
-                            if Self::approximate_is_in_mandelbrot(new_c) {
-                                continue;
-                            }
-                            let (new_iterations, new_hits) =
-                                if let Some(i) = self.count_hitting(z_initial, new_c, iterations) {
-                                    i
-                                } else {
-                                    continue;
-                                };
-                            if new_hits > 0 {
-                                c = new_c;
-                                c_iterations = new_iterations;
-                            }
+fn parse_complex(s: &str) -> Result<Complex64, String> {
+    let (re, im) = s
+        .split_once(',')
+        .ok_or_else(|| format!("Expected `re,im`, got `{}`", s))?;
+    Ok(Complex64::new(
+        re.trim().parse().map_err(|e| format!("{}", e))?,
+        im.trim().parse().map_err(|e| format!("{}", e))?,
+    ))
+}
 
-                            for (b_index, b) in self.config.buffers.iter().enumerate() {
-                                if b.min_iterations <= c_iterations
-                                    && c_iterations < b.max_iterations
-                                {
-                                    Self::iterate_step(
-                                        z_initial,
-                                        c,
-                                        2.0,
-                                        b.max_iterations,
-                                        |i, z| {
-                                            if b.min_iterations <= i && i < b.max_iterations {
-                                                buffers[b_index].hit(z, 1);
-                                            }
-                                        },
-                                    );
-                                }
-                            }
-                        }
+fn main() {
+    if let Err(e) = try_main(Args::parse()) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+fn try_main(args: Args) -> Result<(), Error> {
+    let config = args.config()?;
 
-                        buffers
-                    }));
-                }
-                let mut buffers = vec![
-                    HitBuffer::new(
-                        self.config.width,
-                        self.config.height,
-                        self.config.buffer_min,
-                        self.config.buffer_max
-                    );
-                    self.config.buffers.len()
-                ];
-                for thread in threads {
-                    for (i, buffer) in thread
-                        .join()
-                        .expect("Thread panicked")
-                        .drain(..)
-                        .enumerate()
-                    {
-                        buffers[i].buffer += buffer.buffer;
-                    }
-                }
+    std::fs::create_dir_all(&args.output)?;
+    config.store(args.output.join(format!("{}.toml", args.prefix)))?;
 
-                buffers
-            })
-            .expect("Error while executing threads"),
-        );
+    let cancel = Arc::new(AtomicBool::new(false));
+    {
+        let cancel = cancel.clone();
+        ctrlc::set_handler(move || {
+            if cancel.swap(true, Ordering::SeqCst) {
+                eprintln!("Interrupted twice, exiting without storing");
+                process::exit(130);
+            }
+            eprintln!("Interrupted, storing current state");
+        })
+        .expect("Could not set interrupt handler");
     }
 
-    fn count_hitting(&self, z: Complex32, c: Complex32, iterations: u32) -> Option<(u32, u32)> {
-        let mut hits = 0;
-        let mut hit = false;
-        let result = Self::iterate_step(z, c, 2.0, iterations, |_, c| {
-            if self.config.buffer_min.re < c.re
-                && c.re < self.config.buffer_max.re
-                && self.config.buffer_min.im < c.im
-                && c.im < self.config.buffer_max.im
-            {
-                hits += 1;
-                hit = true;
-            }
-        });
-        if let Some(it) = result {
-            if hit {
-                Some((it, hits))
-            } else {
-                None
+    if config.cycles == 1 {
+        println!("Running");
+        run(&config, &args, &cancel)?;
+    } else {
+        for i in 0..config.cycles {
+            println!("Cycle {}", i);
+            run(&config, &args, &cancel)?;
+            if cancel.load(Ordering::SeqCst) {
+                break;
             }
-        } else {
-            None
         }
     }
-
-    fn rand_complex(min: Complex32, max: Complex32, rng: &mut impl Rng) -> Complex32 {
-        Complex32::new(rng.gen_range(min.re..max.re), rng.gen_range(min.im..max.im))
-    }
-    fn rand_complex_normal(mean: Complex32, deviation: f32, rng: &mut impl Rng) -> Complex32 {
-        Complex32::new(
-            rng.sample(Normal::new(mean.re, deviation).unwrap()),
-            rng.sample(Normal::new(mean.im, deviation).unwrap()),
-        )
+    Ok(())
+}
+fn run(config: &Config, args: &Args, cancel: &Arc<AtomicBool>) -> Result<(), Error> {
+    match config.counter {
+        ElementType::U32 => run_with::<u32>(config, args, cancel),
+        ElementType::U64 => run_with::<u64>(config, args, cancel),
+        ElementType::F32 => run_with::<f32>(config, args, cancel),
+        ElementType::F64 => run_with::<f64>(config, args, cancel),
     }
-    fn mutate(&self, c: Complex32, rng: &mut impl Rng) -> Complex32 {
-        // TODO make mutation probability configurable
-        if rng.gen_range(0.0..1.0) < self.config.mutate_jump {
-            Self::rand_complex(self.config.min, self.config.max, rng)
-        } else {
-            // TODO make deviation configurable
-            Self::rand_complex_normal(c, self.config.mutate_deviation, rng)
-        }
+}
+fn run_with<C: Counter>(
+    config: &Config,
+    args: &Args,
+    cancel: &Arc<AtomicBool>,
+) -> Result<(), Error> {
+    let paths = (0..config.buffers.len())
+        .map(|i| args.output.join(format!("{}-{}.bread", args.prefix, i)))
+        .collect::<Vec<_>>();
+    if let Some(tile_size) = config.tile_size {
+        return run_tiled::<C>(config, &paths, cancel, tile_size);
     }
 
-    fn approximate_is_in_mandelbrot(c: Complex32) -> bool {
-        let q = (c.re - 0.25) * (c.re - 0.25) + c.im * c.im;
-        q * (q + c.re - 0.25) <= 0.25 * c.im * c.im
+    let mut generator = Generator::<C>::new(config.clone())
+        .cancel_flag(cancel.clone())
+        .on_checkpoint(|buffers| {
+            println!("Checkpoint");
+            store(buffers, &paths)
+        })
+        .on_progress(print_progress);
+    if args.resume {
+        generator = generator.resume(load(&paths)?);
+    } else if config.keep && paths.iter().any(|p| p.exists()) {
+        generator = generator.accumulate_into(load(&paths)?);
     }
+    let results = generator.run()?;
 
-    fn step(z: Complex32, c: Complex32) -> Complex32 {
-        z * z + c
-    }
+    print_sampler_stats(generator.sampler_stats());
+    print_rejections(generator.rejections());
+    print_stats1(&results);
+    print_saturated(&results);
+    println!("Storing");
+    store(&results, &paths)?;
+    print_stats2(&results);
+    Ok(())
+}
 
-    fn iterate_step(
-        mut z: Complex32,
-        c: Complex32,
-        bailout: f32,
-        iterations: u32,
-        mut f: impl FnMut(u32, Complex32),
-    ) -> Option<u32> {
-        for i in 0..iterations {
-            z = Self::step(z, c);
-            if z.norm_sqr() > bailout * bailout {
-                return Some(i);
+/// Generates the view one tile after another and writes every tile into the .bread files in place
+fn run_tiled<C: Counter>(
+    config: &Config,
+    paths: &[PathBuf],
+    cancel: &Arc<AtomicBool>,
+    tile_size: usize,
+) -> Result<(), Error> {
+    let keep = config.keep && paths.iter().any(|p| p.exists());
+    let mut files = Vec::new();
+    for (path, band) in paths.iter().zip(&config.buffers) {
+        let header = config_header(config, band);
+        let file = if keep {
+            let file = BufferFile::<C>::open_mut(path)?;
+            if !file.header().is_compatible(&header) {
+                return Err(Error::RegionMismatch);
             }
-            f(i, z);
-        }
-        None
-    }
-
-    fn print_stats1(&self) {
-        for (i, buffer) in self.results.as_ref().unwrap().iter().enumerate() {
-            let samples = buffer
-                .buffer
-                .buffer()
-                .iter()
-                .map(|i| *i as u64)
-                .sum::<u64>();
-            println!("Buffer {} has {} samples, {:.2} samples/s, {:.2} samples/pixel, {:.4} samples/pixel/s",
-                    i,
-                    samples,
-                    samples as f64 / self.config.duration.as_secs_f64(),
-                    samples as f64 / (buffer.buffer.width() * buffer.buffer.height()) as f64,
-                    samples as f64 / (buffer.buffer.width() * buffer.buffer.height()) as f64 / self.config.duration.as_secs_f64());
-        }
-    }
-    fn print_stats2(&self) {
-        for (i, buffer) in self.results.as_ref().unwrap().iter().enumerate() {
-            let samples = buffer
-                .buffer
-                .buffer()
-                .iter()
-                .map(|i| *i as u64)
-                .sum::<u64>();
-            println!(
-                "Buffer {} has {:.2} samples, {:.2} samples/pixel",
-                i,
-                samples,
-                samples as f64 / (buffer.buffer.width() * buffer.buffer.height()) as f64
-            );
-        }
+            if file.width() != config.width || file.height() != config.height {
+                return Err(Error::DimensionMismatch {
+                    expected: (config.width, config.height),
+                    found: (file.width(), file.height()),
+                });
+            }
+            file
+        } else {
+            BufferFile::create(path, header, config.width, config.height)?
+        };
+        files.push(file);
     }
+    // Every tile starts from these headers, so with a seed all tiles use the same streams
+    let headers = files.iter().map(|f| f.header().clone()).collect::<Vec<_>>();
 
-    fn store(&mut self) {
-        for (i, buffer) in self.results.as_mut().unwrap().iter_mut().enumerate() {
-            let path = format!("buffer-{}.bread", i);
-
-            if Path::new(&path).exists() && self.config.keep {
-                let old_buffer = Buffer::load(buffer.buffer.width(), buffer.buffer.height(), &path)
-                    .expect("Could not load old buffer");
+    let tiles = Tile::split(config.width, config.height, tile_size);
+    let mut rejections = Rejections::default();
+    for (i, tile) in tiles.iter().enumerate() {
+        println!("Tile {} of {}", i + 1, tiles.len());
+        let finished = files.iter().map(|f| f.header().clone()).collect::<Vec<_>>();
+        let tile_config = config.tile(tile);
+        let initial = if keep {
+            let mut initial = Vec::new();
+            for (file, header) in files.iter_mut().zip(&headers) {
+                let header = Header {
+                    min: tile_config.buffer_min,
+                    max: tile_config.buffer_max,
+                    ..header.clone()
+                };
+                initial.push(HitBuffer::from_buffer(file.read_tile(tile)?, header));
+            }
+            Some(initial)
+        } else {
+            None
+        };
 
-                buffer.buffer += old_buffer;
+        let results = {
+            let mut generator = Generator::<C>::new(tile_config)
+                .cancel_flag(cancel.clone())
+                .on_checkpoint(|buffers| {
+                    println!("Checkpoint");
+                    store_tile(&mut files, tile, buffers, &headers, &finished)
+                })
+                .on_progress(print_progress);
+            if let Some(initial) = initial {
+                generator = generator.accumulate_into(initial);
             }
-            buffer.buffer.store(&path).expect("Couldn't store buffer");
+            let results = generator.run()?;
+            print_sampler_stats(generator.sampler_stats());
+            rejections.merge(&generator.rejections());
+            results
+        };
+        store_tile(&mut files, tile, &results, &headers, &finished)?;
+        print_saturated(&results);
+
+        if cancel.load(Ordering::SeqCst) {
+            break;
         }
     }
+    print_rejections(rejections);
+    Ok(())
 }
 
-#[derive(Clone)]
-struct HitBuffer {
-    buffer: Buffer<u32>,
-    min: Complex32,
-    max: Complex32,
+fn print_progress(p: &Progress) {
+    print!(
+        "{:.0}%, {} samples, {} orbits, {} hits",
+        p.fraction() * 100.0,
+        p.samples,
+        p.orbits,
+        p.hits
+    );
+    if let Some(change) = p.change {
+        print!(", change {:.5}", change);
+    }
+    println!();
 }
-impl HitBuffer {
-    fn new(width: usize, height: usize, min: Complex32, max: Complex32) -> HitBuffer {
-        HitBuffer {
-            buffer: Buffer::new_default(width, height),
-            min,
-            max,
+fn print_sampler_stats(stats: &[SamplerStats]) {
+    for (i, s) in stats.iter().enumerate() {
+        print!(
+            "Thread {} accepted {} of {} proposals ({:.1}%)",
+            i,
+            s.accepted,
+            s.proposed,
+            s.acceptance() * 100.0
+        );
+        if let Some(deviation) = s.deviation {
+            print!(", deviation {:.3e}", deviation);
         }
+        println!();
     }
-
-    fn hit(&mut self, c: Complex32, i: u32) {
-        let x = (c.re - self.min.re) / (self.max.re - self.min.re) * self.buffer.width() as f32;
-        let y = (c.im - self.min.im) / (self.max.im - self.min.im) * self.buffer.height() as f32;
-
-        if x < 0. || y < 0. || x >= self.buffer.width() as f32 || y >= self.buffer.height() as f32 {
-            return;
-        }
-
-        self.buffer.set(
-            x as usize,
-            y as usize,
-            self.buffer.get(x as usize, y as usize) + i,
+}
+fn print_rejections(rejections: Rejections) {
+    println!(
+        "Rejected {} candidates in the main cardioid, {} in the period-2 bulb, {} as periodic",
+        rejections.cardioid, rejections.bulb, rejections.periodic
+    );
+}
+fn print_stats1<C: Counter>(results: &[HitBuffer<C>]) {
+    for (i, buffer) in results.iter().enumerate() {
+        let samples = buffer
+            .buffer
+            .buffer()
+            .iter()
+            .map(|i| i.to_f64())
+            .sum::<f64>();
+        let duration = buffer.header.generation_time.as_secs_f64();
+        println!(
+            "Buffer {} has {} samples, {:.2} samples/s, {:.2} samples/pixel, {:.4} samples/pixel/s",
+            i,
+            samples,
+            samples / duration,
+            samples / (buffer.buffer.width() * buffer.buffer.height()) as f64,
+            samples / (buffer.buffer.width() * buffer.buffer.height()) as f64 / duration
         );
     }
 }
-
-struct Timer {
-    start: Instant,
-    total: Duration,
-
-    last_check: Instant,
-    check_difference: Duration,
-    frequency: u32,
-    current: u32,
+fn print_stats2<C: Counter>(results: &[HitBuffer<C>]) {
+    for (i, buffer) in results.iter().enumerate() {
+        let samples = buffer
+            .buffer
+            .buffer()
+            .iter()
+            .map(|i| i.to_f64())
+            .sum::<f64>();
+        println!(
+            "Buffer {} has {:.2} samples, {:.2} samples/pixel",
+            i,
+            samples,
+            samples / (buffer.buffer.width() * buffer.buffer.height()) as f64
+        );
+    }
 }
-impl Timer {
-    fn new(start: Instant, total: Duration, check_difference: Duration) -> Timer {
-        Timer {
-            start,
-            total,
-            last_check: Instant::now(),
-            check_difference,
-            frequency: 1,
-            current: 0,
+fn print_saturated<C: Counter>(results: &[HitBuffer<C>]) {
+    for (i, buffer) in results.iter().enumerate() {
+        let saturated = buffer.buffer.saturated();
+        if saturated > 0 {
+            println!(
+                "Warning: buffer {} has {} saturated pixels, use a larger counter type",
+                i, saturated
+            );
         }
     }
+}
 
-    fn check(&mut self) -> bool {
-        self.current += 1;
-        if self.current > self.frequency {
-            self.current = 0;
-            if self.last_check.elapsed() > self.check_difference {
-                self.frequency = 1.max(self.frequency / 2);
-            } else {
-                self.frequency *= 2;
-            }
-            self.last_check = Instant::now();
-
-            self.start.elapsed() > self.total
-        } else {
-            false
-        }
+fn load<C: Counter>(paths: &[PathBuf]) -> Result<Vec<HitBuffer<C>>, Error> {
+    paths.iter().map(HitBuffer::load).collect()
+}
+fn store<C: Counter>(results: &[HitBuffer<C>], paths: &[PathBuf]) -> Result<(), Error> {
+    for (buffer, path) in results.iter().zip(paths) {
+        buffer.store(path)?;
+    }
+    Ok(())
+}
+/// Writes the buffers of one tile into the files. The tile started from `headers`, `finished` are
+/// the headers after the tiles before it. Orbits hitting several tiles are counted once per tile.
+fn store_tile<C: Counter>(
+    files: &mut [BufferFile<C>],
+    tile: &Tile,
+    results: &[HitBuffer<C>],
+    headers: &[Header],
+    finished: &[Header],
+) -> Result<(), Error> {
+    for (((file, buffer), header), finished) in
+        files.iter_mut().zip(results).zip(headers).zip(finished)
+    {
+        file.write_tile(tile, &buffer.buffer)?;
+        file.set_header(Header {
+            samples: finished.samples + buffer.header.samples - header.samples,
+            hits: finished.hits + buffer.header.hits - header.hits,
+            orbits: finished.orbits + buffer.header.orbits - header.orbits,
+            // Every tile proposes the same points
+            proposals: finished.proposals.max(buffer.header.proposals),
+            generation_time: finished.generation_time + buffer.header.generation_time
+                - header.generation_time,
+            seed: buffer.header.seed,
+            ..header.clone()
+        })?;
     }
+    Ok(())
 }
@@ -0,0 +1,641 @@
+use num::complex::Complex64;
+use serde::{Deserialize, Serialize};
+
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::config::AntiConfig;
+use crate::error::Error;
+use crate::escape::Escape;
+use crate::formula::FormulaConfig;
+use crate::kernel::Kernel;
+use crate::projection::Projection;
+use crate::sampler::SamplerKind;
+
+pub static MAGIC: [u8; 8] = *b"BREAD\0\r\n";
+pub const VERSION: u32 = 1;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ElementType {
+    #[default]
+    U32,
+    U64,
+    F32,
+    F64,
+}
+impl ElementType {
+    fn code(self) -> u32 {
+        match self {
+            ElementType::U32 => 0,
+            ElementType::U64 => 1,
+            ElementType::F32 => 2,
+            ElementType::F64 => 3,
+        }
+    }
+    fn from_code(code: u32) -> Option<ElementType> {
+        match code {
+            0 => Some(ElementType::U32),
+            1 => Some(ElementType::U64),
+            2 => Some(ElementType::F32),
+            3 => Some(ElementType::F64),
+            _ => None,
+        }
+    }
+
+    /// Element type of a .bread file, read from its header
+    pub fn of_file(file: impl AsRef<Path>) -> Result<ElementType, Error> {
+        let (_, element_type, _, _) = Header::read(&mut BufReader::new(File::open(file)?))?;
+        Ok(element_type)
+    }
+}
+
+/// Values that can be stored in a .bread file
+pub trait Element: Sized {
+    const TYPE: ElementType;
+    /// Size in bytes
+    const SIZE: usize;
+
+    fn write(&self, w: &mut impl Write) -> io::Result<()>;
+    fn read(r: &mut impl Read) -> io::Result<Self>;
+    /// Converts an element of a u32 file, which can be loaded as any element type
+    fn from_u32(x: u32) -> Self;
+}
+impl Element for u32 {
+    const TYPE: ElementType = ElementType::U32;
+    const SIZE: usize = 4;
+
+    fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(&self.to_le_bytes())
+    }
+    fn read(r: &mut impl Read) -> io::Result<u32> {
+        read_u32(r)
+    }
+    fn from_u32(x: u32) -> u32 {
+        x
+    }
+}
+impl Element for u64 {
+    const TYPE: ElementType = ElementType::U64;
+    const SIZE: usize = 8;
+
+    fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(&self.to_le_bytes())
+    }
+    fn read(r: &mut impl Read) -> io::Result<u64> {
+        read_u64(r)
+    }
+    fn from_u32(x: u32) -> u64 {
+        x as u64
+    }
+}
+impl Element for f32 {
+    const TYPE: ElementType = ElementType::F32;
+    const SIZE: usize = 4;
+
+    fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(&self.to_le_bytes())
+    }
+    fn read(r: &mut impl Read) -> io::Result<f32> {
+        Ok(f32::from_bits(read_u32(r)?))
+    }
+    fn from_u32(x: u32) -> f32 {
+        x as f32
+    }
+}
+impl Element for f64 {
+    const TYPE: ElementType = ElementType::F64;
+    const SIZE: usize = 8;
+
+    fn write(&self, w: &mut impl Write) -> io::Result<()> {
+        w.write_all(&self.to_le_bytes())
+    }
+    fn read(r: &mut impl Read) -> io::Result<f64> {
+        read_f64(r)
+    }
+    fn from_u32(x: u32) -> f64 {
+        x as f64
+    }
+}
+
+/// Describes how the content of a .bread file was generated
+#[derive(Clone, Debug, PartialEq)]
+pub struct Header {
+    /// Complex region covered by the buffer
+    pub min: Complex64,
+    pub max: Complex64,
+
+    /// Iteration band of the orbits accumulated in the buffer
+    pub min_iterations: u32,
+    pub max_iterations: u32,
+
+    /// Number of orbits accumulated in the buffer
+    pub samples: u64,
+    /// Number of points the sampler proposed, including the ones whose orbits did not reach the
+    /// buffer
+    pub proposals: u64,
+    /// Number of orbit points of the proposals that landed in the view, counted once however
+    /// many buffers they were added to
+    pub hits: u64,
+    /// Number of proposals whose orbits landed in the view at least once
+    pub orbits: u64,
+    /// Total time spent generating the buffer
+    pub generation_time: Duration,
+    pub seed: Option<u64>,
+    /// Sampler the orbits were chosen with and the hits added per unit of their weight, 1 for
+    /// the unweighted samplers
+    pub sampler: SamplerKind,
+    pub weight_scale: f32,
+    /// Kernel the orbit points were spread over the pixels with
+    pub kernel: Kernel,
+    /// Plane the orbits were projected onto
+    pub projection: Projection,
+
+    /// Settings the orbits were iterated with, see [`crate::Config`]
+    pub formula: FormulaConfig,
+    pub z0: Complex64,
+    pub julia: Option<Complex64>,
+    pub anti: Option<AntiConfig>,
+    pub escape: Escape,
+    pub bailout: Option<f64>,
+    pub symmetric: bool,
+
+    /// View whose hits an importance map over the sampled region `min..max` counts
+    pub view: Option<(Complex64, Complex64)>,
+}
+impl Header {
+    /// Whether buffers with these headers cover the same region and iteration band with the same
+    /// kernel, projection and orbits and can be added up
+    pub fn is_compatible(&self, other: &Header) -> bool {
+        self.min == other.min
+            && self.max == other.max
+            && self.min_iterations == other.min_iterations
+            && self.max_iterations == other.max_iterations
+            && self.sampler == other.sampler
+            && self.weight_scale == other.weight_scale
+            && self.kernel == other.kernel
+            && self.projection == other.projection
+            && self.formula == other.formula
+            && self.z0 == other.z0
+            && self.julia == other.julia
+            && self.anti == other.anti
+            && self.escape == other.escape
+            && self.bailout == other.bailout
+            && self.symmetric == other.symmetric
+            && self.view == other.view
+    }
+
+    /// Accounts for the samples of another buffer being added to this one
+    pub fn merge(&mut self, other: &Header) {
+        self.samples += other.samples;
+        self.proposals += other.proposals;
+        self.hits += other.hits;
+        self.orbits += other.orbits;
+        self.generation_time += other.generation_time;
+        if self.seed != other.seed {
+            self.seed = None;
+        }
+    }
+
+    pub(crate) fn write(
+        &self,
+        w: &mut impl Write,
+        element_type: ElementType,
+        width: usize,
+        height: usize,
+    ) -> io::Result<()> {
+        w.write_all(&MAGIC)?;
+        w.write_all(&VERSION.to_le_bytes())?;
+        w.write_all(&element_type.code().to_le_bytes())?;
+        w.write_all(&(width as u64).to_le_bytes())?;
+        w.write_all(&(height as u64).to_le_bytes())?;
+
+        for f in &[self.min.re, self.min.im, self.max.re, self.max.im] {
+            w.write_all(&f.to_le_bytes())?;
+        }
+        w.write_all(&self.min_iterations.to_le_bytes())?;
+        w.write_all(&self.max_iterations.to_le_bytes())?;
+
+        w.write_all(&self.samples.to_le_bytes())?;
+        w.write_all(&self.generation_time.as_secs_f64().to_le_bytes())?;
+        w.write_all(&[self.seed.is_some() as u8])?;
+        w.write_all(&self.seed.unwrap_or(0).to_le_bytes())?;
+
+        let (formula, parameter) = match self.formula {
+            FormulaConfig::Mandelbrot => (0u32, Complex64::default()),
+            FormulaConfig::Multibrot { power } => (1, Complex64::new(power, 0.0)),
+            FormulaConfig::BurningShip => (2, Complex64::default()),
+            FormulaConfig::Tricorn => (3, Complex64::default()),
+            FormulaConfig::Celtic => (4, Complex64::default()),
+            FormulaConfig::Phoenix { p } => (5, p),
+        };
+        w.write_all(&formula.to_le_bytes())?;
+        for f in &[parameter.re, parameter.im] {
+            w.write_all(&f.to_le_bytes())?;
+        }
+
+        let anti = self.anti.clone().unwrap_or_default();
+        w.write_all(&[self.anti.is_some() as u8])?;
+        w.write_all(&anti.iterations.to_le_bytes())?;
+        w.write_all(&anti.skip.to_le_bytes())?;
+        w.write_all(&anti.cycle_tolerance.to_le_bytes())?;
+
+        let (kernel, parameter) = match self.kernel {
+            Kernel::Nearest => (0u32, 0.0),
+            Kernel::Bilinear => (1, 0.0),
+            Kernel::Tent { radius } => (2, radius),
+            Kernel::Gaussian { sigma } => (3, sigma),
+        };
+        w.write_all(&kernel.to_le_bytes())?;
+        w.write_all(&parameter.to_le_bytes())?;
+
+        let julia = self.julia.unwrap_or_default();
+        for f in &[self.z0.re, self.z0.im] {
+            w.write_all(&f.to_le_bytes())?;
+        }
+        w.write_all(&[self.julia.is_some() as u8])?;
+        for f in &[julia.re, julia.im] {
+            w.write_all(&f.to_le_bytes())?;
+        }
+
+        for (_, _, angle) in self.projection.angles() {
+            w.write_all(&angle.to_le_bytes())?;
+        }
+
+        let (escape, min, max) = match self.escape {
+            Escape::Circle => (0u32, Complex64::default(), Complex64::default()),
+            Escape::Real => (1, Complex64::default(), Complex64::default()),
+            Escape::Imaginary => (2, Complex64::default(), Complex64::default()),
+            Escape::Manhattan => (3, Complex64::default(), Complex64::default()),
+            Escape::Square => (4, Complex64::default(), Complex64::default()),
+            Escape::Region { min, max } => (5, min, max),
+        };
+        w.write_all(&escape.to_le_bytes())?;
+        for f in &[min.re, min.im, max.re, max.im] {
+            w.write_all(&f.to_le_bytes())?;
+        }
+        w.write_all(&[self.bailout.is_some() as u8])?;
+        w.write_all(&self.bailout.unwrap_or(0.0).to_le_bytes())?;
+
+        w.write_all(&self.proposals.to_le_bytes())?;
+
+        w.write_all(&[self.symmetric as u8])?;
+        let (min, max) = self.view.unwrap_or_default();
+        w.write_all(&[self.view.is_some() as u8])?;
+        for f in &[min.re, min.im, max.re, max.im] {
+            w.write_all(&f.to_le_bytes())?;
+        }
+
+        w.write_all(&self.hits.to_le_bytes())?;
+        w.write_all(&self.orbits.to_le_bytes())?;
+
+        let sampler = match self.sampler {
+            SamplerKind::Uniform => 0u32,
+            SamplerKind::Stratified => 1,
+            SamplerKind::Halton => 2,
+            SamplerKind::Mutation => 3,
+            SamplerKind::Importance => 4,
+        };
+        w.write_all(&sampler.to_le_bytes())?;
+        w.write_all(&self.weight_scale.to_le_bytes())?;
+
+        Ok(())
+    }
+
+    /// Reads the header and returns it with the element type and dimensions of the data that
+    /// follows
+    pub(crate) fn read(r: &mut impl Read) -> Result<(Header, ElementType, usize, usize), Error> {
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(Error::NotABreadFile);
+        }
+        let version = read_u32(r)?;
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let code = read_u32(r)?;
+        let element_type = ElementType::from_code(code)
+            .ok_or_else(|| Error::InvalidHeader(format!("unknown element type {}", code)))?;
+        let width = read_u64(r)? as usize;
+        let height = read_u64(r)? as usize;
+
+        let min = Complex64::new(read_f64(r)?, read_f64(r)?);
+        let max = Complex64::new(read_f64(r)?, read_f64(r)?);
+        let min_iterations = read_u32(r)?;
+        let max_iterations = read_u32(r)?;
+
+        let samples = read_u64(r)?;
+        let generation_time = read_f64(r)?;
+        if !(generation_time >= 0.0 && generation_time.is_finite()) {
+            return Err(Error::InvalidHeader(format!(
+                "invalid generation time {}",
+                generation_time
+            )));
+        }
+        let mut has_seed = [0u8; 1];
+        r.read_exact(&mut has_seed)?;
+        let seed = read_u64(r)?;
+
+        let code = read_u32(r)?;
+        let parameter = Complex64::new(read_f64(r)?, read_f64(r)?);
+        let formula = match code {
+            0 => FormulaConfig::Mandelbrot,
+            1 => FormulaConfig::Multibrot {
+                power: parameter.re,
+            },
+            2 => FormulaConfig::BurningShip,
+            3 => FormulaConfig::Tricorn,
+            4 => FormulaConfig::Celtic,
+            5 => FormulaConfig::Phoenix { p: parameter },
+            _ => return Err(Error::InvalidHeader(format!("unknown formula {}", code))),
+        };
+
+        let has_anti = read_flag(r)?;
+        let anti = AntiConfig {
+            iterations: read_u32(r)?,
+            skip: read_u32(r)?,
+            cycle_tolerance: read_f64(r)?,
+        };
+        let anti = if has_anti { Some(anti) } else { None };
+
+        let code = read_u32(r)?;
+        let parameter = read_f64(r)?;
+        let kernel = match code {
+            0 => Kernel::Nearest,
+            1 => Kernel::Bilinear,
+            2 => Kernel::Tent { radius: parameter },
+            3 => Kernel::Gaussian { sigma: parameter },
+            _ => return Err(Error::InvalidHeader(format!("unknown kernel {}", code))),
+        };
+
+        let z0 = Complex64::new(read_f64(r)?, read_f64(r)?);
+        let has_julia = read_flag(r)?;
+        let julia = Complex64::new(read_f64(r)?, read_f64(r)?);
+        let julia = if has_julia { Some(julia) } else { None };
+
+        let mut angles = [0.0; 6];
+        for angle in &mut angles {
+            *angle = read_f64(r)?;
+        }
+        let projection = Projection::from_angles(angles);
+
+        let code = read_u32(r)?;
+        let min_escape = Complex64::new(read_f64(r)?, read_f64(r)?);
+        let max_escape = Complex64::new(read_f64(r)?, read_f64(r)?);
+        let escape = match code {
+            0 => Escape::Circle,
+            1 => Escape::Real,
+            2 => Escape::Imaginary,
+            3 => Escape::Manhattan,
+            4 => Escape::Square,
+            5 => Escape::Region {
+                min: min_escape,
+                max: max_escape,
+            },
+            _ => {
+                return Err(Error::InvalidHeader(format!(
+                    "unknown escape test {}",
+                    code
+                )))
+            }
+        };
+        let has_bailout = read_flag(r)?;
+        let bailout = read_f64(r)?;
+        let bailout = if has_bailout { Some(bailout) } else { None };
+
+        let proposals = read_u64(r)?;
+        let symmetric = read_flag(r)?;
+        let has_view = read_flag(r)?;
+        let min_view = Complex64::new(read_f64(r)?, read_f64(r)?);
+        let max_view = Complex64::new(read_f64(r)?, read_f64(r)?);
+        let view = if has_view {
+            Some((min_view, max_view))
+        } else {
+            None
+        };
+
+        let hits = read_u64(r)?;
+        let orbits = read_u64(r)?;
+
+        let code = read_u32(r)?;
+        let sampler = match code {
+            0 => SamplerKind::Uniform,
+            1 => SamplerKind::Stratified,
+            2 => SamplerKind::Halton,
+            3 => SamplerKind::Mutation,
+            4 => SamplerKind::Importance,
+            _ => return Err(Error::InvalidHeader(format!("unknown sampler {}", code))),
+        };
+        let weight_scale = f32::from_bits(read_u32(r)?);
+
+        Ok((
+            Header {
+                min,
+                max,
+                min_iterations,
+                max_iterations,
+                samples,
+                proposals,
+                hits,
+                orbits,
+                generation_time: Duration::from_secs_f64(generation_time),
+                seed: if has_seed[0] != 0 { Some(seed) } else { None },
+                sampler,
+                weight_scale,
+                kernel,
+                projection,
+                formula,
+                z0,
+                julia,
+                anti,
+                escape,
+                bailout,
+                symmetric,
+                view,
+            },
+            element_type,
+            width,
+            height,
+        ))
+    }
+}
+
+fn read_flag(r: &mut impl Read) -> io::Result<bool> {
+    let mut flag = [0u8; 1];
+    r.read_exact(&mut flag)?;
+    Ok(flag[0] != 0)
+}
+fn read_u32(r: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    r.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+fn read_u64(r: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    r.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+fn read_f64(r: &mut impl Read) -> io::Result<f64> {
+    Ok(f64::from_bits(read_u64(r)?))
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::buffer::Buffer;
+
+    use std::fs;
+
+    pub(crate) fn header() -> Header {
+        Header {
+            min: Complex64::new(-1.5, -0.25),
+            max: Complex64::new(0.5, 1.75),
+            min_iterations: 10,
+            max_iterations: 2000,
+            samples: 123_456_789,
+            proposals: 987_654_321,
+            hits: 55_555,
+            orbits: 4_321,
+            generation_time: Duration::from_secs_f64(12.5),
+            seed: Some(42),
+            sampler: SamplerKind::Importance,
+            weight_scale: 12.5,
+            kernel: Kernel::Gaussian { sigma: 0.75 },
+            projection: Projection {
+                re_z_re_c: 30.0,
+                im_z_im_c: -45.0,
+                ..Projection::default()
+            },
+            formula: FormulaConfig::Phoenix {
+                p: Complex64::new(-0.5, 0.125),
+            },
+            z0: Complex64::new(0.25, -0.5),
+            julia: Some(Complex64::new(-0.8, 0.156)),
+            anti: Some(AntiConfig {
+                iterations: 500,
+                skip: 50,
+                cycle_tolerance: 0.25,
+            }),
+            escape: Escape::Region {
+                min: Complex64::new(-3.0, -2.0),
+                max: Complex64::new(4.0, 5.0),
+            },
+            bailout: Some(8.0),
+            symmetric: true,
+            view: Some((Complex64::new(-2.0, -2.0), Complex64::new(2.0, 2.0))),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let empty = Header {
+            seed: None,
+            kernel: Kernel::Nearest,
+            julia: None,
+            anti: None,
+            escape: Escape::Circle,
+            bailout: None,
+            symmetric: false,
+            view: None,
+            ..header()
+        };
+        for header in [header(), empty] {
+            let mut bytes = Vec::new();
+            header
+                .write(&mut bytes, ElementType::U32, 640, 480)
+                .unwrap();
+            let read = Header::read(&mut &bytes[..]).unwrap();
+            assert_eq!(read, (header.clone(), ElementType::U32, 640, 480));
+        }
+    }
+
+    #[test]
+    fn rejects_other_files() {
+        let mut bytes = Vec::new();
+        header().write(&mut bytes, ElementType::U32, 1, 1).unwrap();
+        for version in [0, VERSION + 1] {
+            let mut bytes = bytes.clone();
+            bytes[8..12].copy_from_slice(&version.to_le_bytes());
+            assert!(matches!(
+                Header::read(&mut &bytes[..]),
+                Err(Error::UnsupportedVersion(v)) if v == version
+            ));
+        }
+        bytes[0] = b'b';
+        assert!(matches!(
+            Header::read(&mut &bytes[..]),
+            Err(Error::NotABreadFile)
+        ));
+    }
+
+    #[test]
+    fn file_round_trip() {
+        let file = std::env::temp_dir().join(format!("bread-header-{}.bread", std::process::id()));
+        let mut buffer = Buffer::new_default(3, 2);
+        for (i, value) in [0, 1, 2, 3, u32::MAX - 1, u32::MAX].iter().enumerate() {
+            buffer.set(i % 3, i / 3, *value);
+        }
+        buffer.store(&header(), &file).unwrap();
+        let loaded = Buffer::<u32>::load(&file);
+        fs::remove_file(&file).unwrap();
+
+        let (read, loaded) = loaded.unwrap();
+        assert_eq!(read, header());
+        assert_eq!(loaded.buffer(), buffer.buffer());
+    }
+
+    #[test]
+    fn compatibility() {
+        let other = Header {
+            samples: 1,
+            generation_time: Duration::default(),
+            seed: None,
+            ..header()
+        };
+        assert!(header().is_compatible(&other));
+        let other = Header {
+            formula: FormulaConfig::Mandelbrot,
+            ..header()
+        };
+        assert!(!header().is_compatible(&other));
+        let other = Header {
+            kernel: Kernel::Bilinear,
+            ..header()
+        };
+        assert!(!header().is_compatible(&other));
+        let other = Header {
+            julia: None,
+            ..header()
+        };
+        assert!(!header().is_compatible(&other));
+        let other = Header {
+            projection: Projection::default(),
+            ..header()
+        };
+        assert!(!header().is_compatible(&other));
+        let other = Header {
+            bailout: Some(4.0),
+            ..header()
+        };
+        assert!(!header().is_compatible(&other));
+        let other = Header {
+            view: None,
+            ..header()
+        };
+        assert!(!header().is_compatible(&other));
+        let other = Header {
+            sampler: SamplerKind::Mutation,
+            ..header()
+        };
+        assert!(!header().is_compatible(&other));
+        let other = Header {
+            weight_scale: 100.0,
+            ..header()
+        };
+        assert!(!header().is_compatible(&other));
+    }
+}
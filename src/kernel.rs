@@ -0,0 +1,153 @@
+use serde::{Deserialize, Serialize};
+
+/// Largest distance in pixels a kernel may spread a point over
+pub const MAX_KERNEL_RADIUS: f64 = 7.0;
+/// Most pixels a kernel reaches along one axis
+const MAX_TAPS: usize = 2 * MAX_KERNEL_RADIUS as usize + 1;
+
+/// How an orbit point is spread over the pixels around it
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Kernel {
+    /// The whole point goes to the pixel containing it
+    #[default]
+    Nearest,
+    /// Split between the four pixels whose centers surround the point
+    Bilinear,
+    /// Weights falling linearly to zero at `radius` pixels
+    Tent { radius: f64 },
+    /// Gaussian weights with a standard deviation of `sigma` pixels, cut off at three sigma
+    Gaussian { sigma: f64 },
+}
+impl Kernel {
+    /// Distance in pixels from the point beyond which pixels get nothing
+    pub fn radius(&self) -> f64 {
+        match *self {
+            Kernel::Nearest => 0.5,
+            Kernel::Bilinear => 1.0,
+            Kernel::Tent { radius } => radius,
+            Kernel::Gaussian { sigma } => 3.0 * sigma,
+        }
+    }
+
+    /// Unnormalized weight of a pixel center `d` pixels away from the point along one axis
+    fn profile(&self, d: f64) -> f64 {
+        match *self {
+            Kernel::Nearest => 1.0,
+            Kernel::Bilinear => 1.0 - d.abs(),
+            Kernel::Tent { radius } => 1.0 - d.abs() / radius,
+            Kernel::Gaussian { sigma } => (-d * d / (2.0 * sigma * sigma)).exp(),
+        }
+    }
+
+    /// Calls `f` with every pixel of a `width`×`height` buffer that the point at pixel
+    /// coordinates `(x, y)` spreads to and the fraction of the point the pixel gets. Pixel centers
+    /// lie at half-integer coordinates. Fractions falling outside the buffer are dropped.
+    pub fn spread(
+        &self,
+        x: f64,
+        y: f64,
+        width: usize,
+        height: usize,
+        mut f: impl FnMut(usize, usize, f64),
+    ) {
+        if let Kernel::Nearest = self {
+            if 0.0 <= x && x < width as f64 && 0.0 <= y && y < height as f64 {
+                f(x as usize, y as usize, 1.0);
+            }
+            return;
+        }
+
+        let radius = self.radius();
+        let reaches = |x: f64, size: usize| -radius < x && x < size as f64 + radius;
+        if !reaches(x, width) || !reaches(y, height) {
+            return;
+        }
+
+        let mut taps_x = [(0, 0.0); MAX_TAPS];
+        let mut taps_y = [(0, 0.0); MAX_TAPS];
+        let count_x = self.taps(x, width, &mut taps_x);
+        let count_y = self.taps(y, height, &mut taps_y);
+        for (py, fy) in &taps_y[..count_y] {
+            for (px, fx) in &taps_x[..count_x] {
+                f(*px, *py, fx * fy);
+            }
+        }
+    }
+
+    /// Writes the pixels within `0..size` around `x` along one axis and their weights to `taps`,
+    /// normalized over all pixels in reach, and returns how many there are
+    fn taps(&self, x: f64, size: usize, taps: &mut [(usize, f64); MAX_TAPS]) -> usize {
+        let radius = self.radius();
+        let first = (x - 0.5 - radius).floor() as i64 + 1;
+        let last = (x - 0.5 + radius).ceil() as i64 - 1;
+
+        let mut total = 0.0;
+        let mut count = 0;
+        for i in first..=last {
+            let weight = self.profile(i as f64 + 0.5 - x);
+            total += weight;
+            if 0 <= i && i < size as i64 && weight > 0.0 && count < MAX_TAPS {
+                taps[count] = (i as usize, weight);
+                count += 1;
+            }
+        }
+        for (_, weight) in &mut taps[..count] {
+            *weight /= total;
+        }
+        count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KERNELS: [Kernel; 6] = [
+        Kernel::Bilinear,
+        Kernel::Tent { radius: 0.7 },
+        Kernel::Tent { radius: 2.5 },
+        Kernel::Gaussian { sigma: 0.4 },
+        Kernel::Gaussian { sigma: 2.0 },
+        Kernel::Gaussian { sigma: 7.0 / 3.0 },
+    ];
+
+    fn total(kernel: &Kernel, x: f64, size: usize) -> f64 {
+        let mut taps = [(0, 0.0); MAX_TAPS];
+        let count = kernel.taps(x, size, &mut taps);
+        taps[..count].iter().map(|(_, weight)| weight).sum()
+    }
+
+    #[test]
+    fn taps_sum_to_one() {
+        for kernel in &KERNELS {
+            for i in 0..500 {
+                let x = 8.0 + i as f64 * 0.0317;
+                let total = total(kernel, x, 40);
+                assert!(
+                    (total - 1.0).abs() < 1e-12,
+                    "{:?} at {}: {}",
+                    kernel,
+                    x,
+                    total
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn taps_drop_fractions_outside() {
+        for kernel in &KERNELS {
+            for x in [0.1, 39.9] {
+                let total = total(kernel, x, 40);
+                assert!(
+                    0.0 < total && total < 1.0,
+                    "{:?} at {}: {}",
+                    kernel,
+                    x,
+                    total
+                );
+            }
+        }
+    }
+}
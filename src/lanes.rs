@@ -0,0 +1,370 @@
+use num::complex::Complex;
+
+use crate::escape::Bailout;
+use crate::float::Float;
+use crate::formula::Formula;
+use crate::generate::OrbitEnd;
+use crate::projection::ProjectionMatrix;
+
+/// Number of orbits iterated together by [`LaneOrbits`]
+pub const LANES: usize = 8;
+
+/// One complex value per lane, stored as separate real and imaginary arrays so the compiler can
+/// vectorize the arithmetic on all lanes
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Lanes<T> {
+    pub re: [T; LANES],
+    pub im: [T; LANES],
+}
+impl<T: Float> Lanes<T> {
+    pub fn get(&self, lane: usize) -> Complex<T> {
+        Complex::new(self.re[lane], self.im[lane])
+    }
+    pub fn set(&mut self, lane: usize, c: Complex<T>) {
+        self.re[lane] = c.re;
+        self.im[lane] = c.im;
+    }
+}
+
+/// Orbits of up to [`LANES`] values of c that are iterated together. A lane whose orbit ended
+/// can be started again right away, so the other lanes do not wait for the longest orbit.
+#[derive(Clone, Debug, Default)]
+pub struct LaneOrbits<T> {
+    /// Value every orbit started from
+    z0: Lanes<T>,
+    z: Lanes<T>,
+    z_previous: Lanes<T>,
+    c: Lanes<T>,
+    iteration: [u32; LANES],
+    hits: [u32; LANES],
+    active: [bool; LANES],
+    /// Point of Brent's cycle detection and the point before it, see
+    /// [`crate::generate::iterate_step`]
+    saved: Lanes<T>,
+    saved_previous: Lanes<T>,
+}
+impl<T: Float> LaneOrbits<T> {
+    pub fn is_active(&self, lane: usize) -> bool {
+        self.active[lane]
+    }
+    /// Number of orbits currently being iterated
+    pub fn running(&self) -> usize {
+        self.active.iter().filter(|a| **a).count()
+    }
+
+    /// Starts iterating the orbit of `c` from `z` in `lane`
+    pub fn start(&mut self, lane: usize, z: Complex<T>, c: Complex<T>) {
+        self.z0.set(lane, z);
+        self.z.set(lane, z);
+        self.z_previous
+            .set(lane, Complex::new(T::zero(), T::zero()));
+        self.c.set(lane, c);
+        self.iteration[lane] = 0;
+        self.hits[lane] = 0;
+        self.active[lane] = true;
+
+        self.saved.set(lane, z);
+        self.saved_previous
+            .set(lane, Complex::new(T::zero(), T::zero()));
+    }
+
+    /// Iterates all lanes until at least one orbit ended, then calls `f` with the lane, the
+    /// starting z, c, the end of the orbit and its number of points whose projection lies
+    /// strictly inside `min..max`, like [`crate::generate::iterate_step`], for every orbit that
+    /// ended. With `conjugate` the conjugates of the points inside count as well.
+    #[allow(clippy::too_many_arguments)]
+    pub fn advance(
+        &mut self,
+        formula: &impl Formula<T>,
+        bailout: &Bailout<T>,
+        tolerance: T,
+        iterations: u32,
+        min: Complex<T>,
+        max: Complex<T>,
+        projection: &ProjectionMatrix<T>,
+        conjugate: bool,
+        f: impl FnMut(usize, Complex<T>, Complex<T>, OrbitEnd, u32),
+    ) {
+        // Choosing the test outside of the loop keeps the usual circle test vectorized
+        if let Some(radius) = bailout.circle_radius() {
+            let radius = radius * radius;
+            let escaped = |re: T, im: T| re * re + im * im > radius;
+            self.advance_with(
+                formula, escaped, tolerance, iterations, min, max, projection, conjugate, f,
+            );
+        } else {
+            let escaped = |re, im| bailout.escaped_parts(re, im);
+            self.advance_with(
+                formula, escaped, tolerance, iterations, min, max, projection, conjugate, f,
+            );
+        }
+    }
+    #[allow(clippy::too_many_arguments)]
+    #[inline(always)]
+    fn advance_with(
+        &mut self,
+        formula: &impl Formula<T>,
+        escape_test: impl Fn(T, T) -> bool,
+        tolerance: T,
+        iterations: u32,
+        min: Complex<T>,
+        max: Complex<T>,
+        projection: &ProjectionMatrix<T>,
+        conjugate: bool,
+        mut f: impl FnMut(usize, Complex<T>, Complex<T>, OrbitEnd, u32),
+    ) {
+        if self.running() == 0 {
+            return;
+        }
+        let check_cycles = tolerance > T::zero();
+        let tolerance = tolerance * tolerance;
+        let previous = formula.uses_previous();
+        let mut escaped = [false; LANES];
+        let mut periodic = [false; LANES];
+        let mut ended = [false; LANES];
+
+        loop {
+            let next = formula.step_lanes(&self.z, &self.z_previous, &self.c);
+            // Lanes that are not active keep being iterated, the masks keep them from being
+            // counted. Combining the masks with `&` and `|` instead of branching lets the
+            // compiler keep all lanes in vectors.
+            let mut any = false;
+            for lane in 0..LANES {
+                let (re, im) = (next.re[lane], next.im[lane]);
+                let active = self.active[lane];
+                escaped[lane] = escape_test(re, im);
+                let (c_re, c_im) = (self.c.re[lane], self.c.im[lane]);
+                let (x, y) = projection.project_parts(re, im, c_re, c_im);
+                let inside = (min.re < x) & (x < max.re) & (min.im < y) & (y < max.im);
+                let (x, y) = projection.project_parts(re, -im, c_re, -c_im);
+                let inside_conjugate =
+                    conjugate & (min.re < x) & (x < max.re) & (min.im < y) & (y < max.im);
+                let counted = active & !escaped[lane];
+                self.hits[lane] += (counted & inside) as u32 + (counted & inside_conjugate) as u32;
+                self.iteration[lane] = self.iteration[lane].wrapping_add(1);
+
+                let (d_re, d_im) = (re - self.saved.re[lane], im - self.saved.im[lane]);
+                let mut distance = d_re * d_re + d_im * d_im;
+                // The point before `next` is the current one
+                let (p_re, p_im) = (self.z.re[lane], self.z.im[lane]);
+                if previous {
+                    let d_re = p_re - self.saved_previous.re[lane];
+                    let d_im = p_im - self.saved_previous.im[lane];
+                    distance = distance + d_re * d_re + d_im * d_im;
+                }
+                periodic[lane] = check_cycles & (distance <= tolerance);
+                // The saved point moves at iterations 1, 3, 7, 15, ..., the same distances
+                // `iterate_step` uses
+                let save = self.iteration[lane] & self.iteration[lane].wrapping_add(1) == 0;
+                self.saved.re[lane] = if save { re } else { self.saved.re[lane] };
+                self.saved.im[lane] = if save { im } else { self.saved.im[lane] };
+                let (s_re, s_im) = (self.saved_previous.re[lane], self.saved_previous.im[lane]);
+                self.saved_previous.re[lane] = if save { p_re } else { s_re };
+                self.saved_previous.im[lane] = if save { p_im } else { s_im };
+
+                ended[lane] = active
+                    & (escaped[lane] | periodic[lane] | (self.iteration[lane] >= iterations));
+                any |= ended[lane];
+            }
+            (self.z, self.z_previous) = (next, self.z);
+            if any {
+                break;
+            }
+        }
+
+        for lane in 0..LANES {
+            if ended[lane] {
+                self.active[lane] = false;
+                let end = if escaped[lane] {
+                    OrbitEnd::Escaped(self.iteration[lane] - 1)
+                } else if periodic[lane] {
+                    OrbitEnd::Periodic
+                } else {
+                    OrbitEnd::Bounded
+                };
+                f(
+                    lane,
+                    self.z0.get(lane),
+                    self.c.get(lane),
+                    end,
+                    self.hits[lane],
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::escape::Escape;
+    use crate::formula::{BurningShip, Mandelbrot, Phoenix};
+    use crate::generate::iterate_step;
+    use crate::projection::Projection;
+
+    const ITERATIONS: u32 = 300;
+
+    /// $z_{n+1} = 2 z_{n-1} + c$, whose orbits can return to a point while the point before it
+    /// differs
+    pub(crate) struct Skipping;
+    impl<T: Float> Formula<T> for Skipping {
+        fn step(&self, _z: Complex<T>, z_previous: Complex<T>, c: Complex<T>) -> Complex<T> {
+            z_previous + z_previous + c
+        }
+        fn uses_previous(&self) -> bool {
+            true
+        }
+    }
+
+    fn min() -> Complex<f64> {
+        Complex::new(-1.0, -1.0)
+    }
+    fn max() -> Complex<f64> {
+        Complex::new(1.0, 1.5)
+    }
+
+    /// End and hits of the orbits of `points` starting at 0, iterated in lanes
+    fn advance_all(
+        formula: &impl Formula<f64>,
+        bailout: &Bailout<f64>,
+        tolerance: f64,
+        projection: &ProjectionMatrix<f64>,
+        conjugate: bool,
+        points: &[Complex<f64>],
+    ) -> Vec<(OrbitEnd, u32)> {
+        let mut results = vec![None; points.len()];
+        let mut indices = [0; LANES];
+        let mut next = 0;
+        let mut orbits = LaneOrbits::default();
+        loop {
+            for (lane, index) in indices.iter_mut().enumerate() {
+                if !orbits.is_active(lane) && next < points.len() {
+                    orbits.start(lane, Complex::new(0.0, 0.0), points[next]);
+                    *index = next;
+                    next += 1;
+                }
+            }
+            if orbits.running() == 0 {
+                break;
+            }
+            orbits.advance(
+                formula,
+                bailout,
+                tolerance,
+                ITERATIONS,
+                min(),
+                max(),
+                projection,
+                conjugate,
+                |lane, _, _, end, hits| results[indices[lane]] = Some((end, hits)),
+            );
+        }
+        results.into_iter().map(|r| r.unwrap()).collect()
+    }
+
+    /// The same as [`advance_all`], one orbit after another
+    fn iterate_all(
+        formula: &impl Formula<f64>,
+        bailout: &Bailout<f64>,
+        tolerance: f64,
+        projection: &ProjectionMatrix<f64>,
+        conjugate: bool,
+        points: &[Complex<f64>],
+    ) -> Vec<(OrbitEnd, u32)> {
+        let (min, max) = (min(), max());
+        let inside = |z: Complex<f64>, c: Complex<f64>| {
+            let p = projection.project(z, c);
+            min.re < p.re && p.re < max.re && min.im < p.im && p.im < max.im
+        };
+        points
+            .iter()
+            .map(|c| {
+                let mut hits = 0;
+                let end = iterate_step(
+                    formula,
+                    Complex::new(0.0, 0.0),
+                    *c,
+                    bailout,
+                    tolerance,
+                    ITERATIONS,
+                    |_, z| {
+                        hits += inside(z, *c) as u32;
+                        hits += (conjugate && inside(z.conj(), c.conj())) as u32;
+                    },
+                );
+                (end, hits)
+            })
+            .collect()
+    }
+
+    /// Compares the lanes with `iterate_step` for orbits on a grid of c under several projections
+    /// and escape tests, with and without cycle detection and conjugates
+    fn check(formula: &impl Formula<f64>) {
+        let points = (0..40 * 40)
+            .map(|i| Complex::new(-2.1 + (i % 40) as f64 * 0.1, -1.95 + (i / 40) as f64 * 0.1))
+            .collect::<Vec<_>>();
+        let projections = [
+            Projection::default(),
+            Projection {
+                re_z_re_c: 30.0,
+                im_z_im_c: 90.0,
+                ..Projection::default()
+            },
+        ];
+        let bailouts = [Bailout::circle(2.0), Bailout::new(Escape::Manhattan, 3.0)];
+        for projection in &projections {
+            let projection = ProjectionMatrix::new(projection);
+            for bailout in &bailouts {
+                for tolerance in [0.0, 1e-10] {
+                    for conjugate in [false, true] {
+                        let lanes = advance_all(
+                            formula,
+                            bailout,
+                            tolerance,
+                            &projection,
+                            conjugate,
+                            &points,
+                        );
+                        let steps = iterate_all(
+                            formula,
+                            bailout,
+                            tolerance,
+                            &projection,
+                            conjugate,
+                            &points,
+                        );
+                        for ((lanes, steps), c) in lanes.iter().zip(&steps).zip(&points) {
+                            assert_eq!(lanes, steps, "c = {}", c);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn advance_matches_iterate_step() {
+        check(&Mandelbrot);
+        check(&BurningShip);
+        check(&Phoenix {
+            p: Complex::new(-0.5, 0.1),
+        });
+        check(&Skipping);
+    }
+
+    #[test]
+    fn cycles_include_the_previous_point() {
+        // 0, 1, 1, 3: the second 1 follows 1 instead of 0
+        let projection = ProjectionMatrix::new(&Projection::default());
+        let c = [Complex::new(1.0, 0.0)];
+        let ends = advance_all(
+            &Skipping,
+            &Bailout::circle(2.0),
+            1e-5,
+            &projection,
+            false,
+            &c,
+        );
+        assert_eq!(ends[0].0, OrbitEnd::Escaped(2));
+    }
+}
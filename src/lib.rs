@@ -1,5 +1,46 @@
+mod accumulator;
+pub use accumulator::*;
+
 mod buffer;
 pub use buffer::*;
 
 mod config;
-pub use config::*;
\ No newline at end of file
+pub use config::*;
+
+mod counter;
+pub use counter::*;
+
+mod encode;
+pub use encode::*;
+
+mod error;
+pub use error::*;
+
+mod escape;
+pub use escape::*;
+
+mod float;
+pub use float::*;
+
+mod formula;
+pub use formula::*;
+
+mod header;
+pub use header::*;
+
+mod kernel;
+pub use kernel::*;
+
+mod lanes;
+pub use lanes::*;
+
+mod projection;
+pub use projection::*;
+
+mod sampler;
+pub use sampler::*;
+
+mod tile;
+pub use tile::*;
+
+pub mod generate;
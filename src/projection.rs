@@ -0,0 +1,165 @@
+use num::complex::Complex;
+use serde::{Deserialize, Serialize};
+
+use crate::float::Float;
+
+/// Plane of the four-dimensional space of (Re z, Im z, Re c, Im c) the orbits are projected onto.
+///
+/// The space is rotated by the angles in degrees in the given planes, one after another in the
+/// order of the fields, and the image shows the first two coordinates. Rotating a plane by 90°
+/// replaces its first axis with the second, so `im_z_re_c = 90` plots (Re z, Re c).
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Projection {
+    pub re_z_im_z: f64,
+    pub re_z_re_c: f64,
+    pub re_z_im_c: f64,
+    pub im_z_re_c: f64,
+    pub im_z_im_c: f64,
+    pub re_c_im_c: f64,
+}
+impl Projection {
+    /// Angles in the order they are applied, with the indices of the axes of their plane
+    pub fn angles(&self) -> [(usize, usize, f64); 6] {
+        [
+            (0, 1, self.re_z_im_z),
+            (0, 2, self.re_z_re_c),
+            (0, 3, self.re_z_im_c),
+            (1, 2, self.im_z_re_c),
+            (1, 3, self.im_z_im_c),
+            (2, 3, self.re_c_im_c),
+        ]
+    }
+    pub fn from_angles(angles: [f64; 6]) -> Projection {
+        let [re_z_im_z, re_z_re_c, re_z_im_c, im_z_re_c, im_z_im_c, re_c_im_c] = angles;
+        Projection {
+            re_z_im_z,
+            re_z_re_c,
+            re_z_im_c,
+            im_z_re_c,
+            im_z_im_c,
+            re_c_im_c,
+        }
+    }
+
+    pub fn is_identity(&self) -> bool {
+        *self == Projection::default()
+    }
+
+    /// Projection a fraction `t` of the way to `other`, interpolating every angle linearly
+    pub fn interpolate(&self, other: &Projection, t: f64) -> Projection {
+        let (from, to) = (self.angles(), other.angles());
+        let mut angles = [0.0; 6];
+        for (angle, ((_, _, a), (_, _, b))) in angles.iter_mut().zip(from.iter().zip(&to)) {
+            *angle = a + (b - a) * t;
+        }
+        Projection::from_angles(angles)
+    }
+
+    /// Rows of the rotation giving the x and y coordinates of the image
+    pub fn matrix(&self) -> [[f64; 4]; 2] {
+        let mut rows = [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ];
+        for (i, j, angle) in self.angles() {
+            let (sin, cos) = sin_cos_degrees(angle);
+            let (row_i, row_j) = (rows[i], rows[j]);
+            for k in 0..4 {
+                rows[i][k] = cos * row_i[k] + sin * row_j[k];
+                rows[j][k] = cos * row_j[k] - sin * row_i[k];
+            }
+        }
+        [rows[0], rows[1]]
+    }
+}
+
+/// [`Projection::matrix`] in the precision of the iteration
+#[derive(Clone, Copy, Debug)]
+pub struct ProjectionMatrix<T> {
+    x: [T; 4],
+    y: [T; 4],
+    /// Skips the multiplications for the default projection
+    identity: bool,
+}
+impl<T: Float> ProjectionMatrix<T> {
+    pub fn new(projection: &Projection) -> ProjectionMatrix<T> {
+        let [x, y] = projection.matrix();
+        ProjectionMatrix {
+            x: x.map(T::from_f64),
+            y: y.map(T::from_f64),
+            identity: projection.is_identity(),
+        }
+    }
+
+    /// Point of the image the orbit point `z` of `c` is plotted at
+    #[inline(always)]
+    pub fn project(&self, z: Complex<T>, c: Complex<T>) -> Complex<T> {
+        let (x, y) = self.project_parts(z.re, z.im, c.re, c.im);
+        Complex::new(x, y)
+    }
+    /// [`ProjectionMatrix::project`] on separate coordinates, for the SIMD lanes
+    #[inline(always)]
+    pub fn project_parts(&self, z_re: T, z_im: T, c_re: T, c_im: T) -> (T, T) {
+        if self.identity {
+            return (z_re, z_im);
+        }
+        let dot = |row: &[T; 4]| row[0] * z_re + row[1] * z_im + row[2] * c_re + row[3] * c_im;
+        (dot(&self.x), dot(&self.y))
+    }
+}
+
+/// Sine and cosine of an angle in degrees, exact for multiples of 90°
+fn sin_cos_degrees(angle: f64) -> (f64, f64) {
+    if angle % 90.0 == 0.0 {
+        match (angle / 90.0).rem_euclid(4.0) as u32 {
+            0 => (0.0, 1.0),
+            1 => (1.0, 0.0),
+            2 => (0.0, -1.0),
+            _ => (-1.0, 0.0),
+        }
+    } else {
+        angle.to_radians().sin_cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matrix() {
+        assert!(Projection::default().is_identity());
+        assert_eq!(
+            Projection::default().matrix(),
+            [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0]]
+        );
+        let projection = Projection {
+            im_z_re_c: 90.0,
+            ..Projection::default()
+        };
+        assert_eq!(
+            projection.matrix(),
+            [[1.0, 0.0, 0.0, 0.0], [0.0, 0.0, 1.0, 0.0]]
+        );
+        let matrix = ProjectionMatrix::<f64>::new(&projection);
+        let (z, c) = (Complex::new(1.0, 2.0), Complex::new(3.0, 4.0));
+        assert_eq!(matrix.project(z, c), Complex::new(1.0, 3.0));
+    }
+
+    #[test]
+    fn interpolate() {
+        let from = Projection::default();
+        let to = Projection {
+            re_z_re_c: 90.0,
+            im_z_im_c: -30.0,
+            ..Projection::default()
+        };
+        assert_eq!(from.interpolate(&to, 0.0), from);
+        assert_eq!(from.interpolate(&to, 1.0), to);
+        let middle = from.interpolate(&to, 0.5);
+        assert_eq!((middle.re_z_re_c, middle.im_z_im_c), (45.0, -15.0));
+    }
+}
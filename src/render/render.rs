@@ -1,44 +1,143 @@
+use clap::Parser;
 use image::ColorType;
 
+use std::path::PathBuf;
+use std::process;
+
 use bread::*;
 
+/// Number of rows read from the buffers and encoded at once, so images larger than memory can be
+/// rendered
+const STRIP_ROWS: usize = 256;
+
+/// Renders .bread buffers to PNG images
+#[derive(Parser)]
+#[clap(name = "render", version)]
+struct Args {
+    /// Config file, defaults to bread.toml if it exists
+    #[clap(short, long)]
+    config: Option<PathBuf>,
+    /// Directory the buffers are read from
+    #[clap(short, long, default_value = ".")]
+    input: PathBuf,
+    /// File name prefix of the buffers
+    #[clap(short, long, default_value = "buffer")]
+    prefix: String,
+    /// Path of the combined image, single buffer images are stored next to it
+    #[clap(short, long, default_value = "image.png")]
+    output: PathBuf,
+}
+
 fn main() {
-    let c = get_config();
-    let buffers = (0..c.buffers.len())
-        .map(|i| {
-            Buffer::load(c.width, c.height, &format!("buffer-{}.bread", i))
-                .expect("Could not load buffer")
-        })
-        .collect::<Vec<_>>();
+    if let Err(e) = try_main(Args::parse()) {
+        eprintln!("Error: {}", e);
+        process::exit(1);
+    }
+}
+fn try_main(args: Args) -> Result<(), Error> {
+    let first = args.input.join(format!("{}-0.bread", args.prefix));
+    let element_type = if first.exists() {
+        ElementType::of_file(first)?
+    } else {
+        ElementType::default()
+    };
+    match element_type {
+        ElementType::U32 => render::<u32>(args),
+        ElementType::U64 => render::<u64>(args),
+        ElementType::F32 => render::<f32>(args),
+        ElementType::F64 => render::<f64>(args),
+    }
+}
+fn render<C: Counter>(args: Args) -> Result<(), Error> {
+    let c = get_config(args.config.as_deref())?;
+    let r = &c.render;
+
+    let mut files: Vec<BufferFile<C>> = Vec::new();
+    loop {
+        let path = args
+            .input
+            .join(format!("{}-{}.bread", args.prefix, files.len()));
+        if !path.exists() {
+            break;
+        }
+
+        let file = BufferFile::<C>::open(&path)?;
+        let header = file.header();
+        println!(
+            "Loaded {}: {}x{}, iterations {}..{}, {} samples in {:.1}s",
+            path.display(),
+            file.width(),
+            file.height(),
+            header.min_iterations,
+            header.max_iterations,
+            header.samples,
+            header.generation_time.as_secs_f64()
+        );
+        if let Some(first) = files.first() {
+            if first.width() != file.width() || first.height() != file.height() {
+                return Err(Error::DimensionMismatch {
+                    expected: (first.width(), first.height()),
+                    found: (file.width(), file.height()),
+                });
+            }
+        }
+        files.push(file);
+    }
+    if r.channels.iter().any(|c| *c >= files.len()) {
+        return Err(Error::InvalidConfig(format!(
+            "found {} buffers, not enough for the configured channels",
+            files.len()
+        )));
+    }
+    let (width, height) = (files[0].width(), files[0].height());
+    let maxima = files
+        .iter_mut()
+        .map(|f| f.max())
+        .collect::<Result<Vec<_>, _>>()?;
 
     println!("Generating images");
-    image::save_buffer(
-        "image.png",
-        &Buffer::join(
-            buffers[2].to_f32().exponential(2.0).to_u8(),
-            buffers[1].to_f32().exponential(2.0).to_u8(),
-            buffers[0].to_f32().exponential(2.0).to_u8(),
-        )
-        .flatten(),
-        buffers[0].width() as u32,
-        buffers[0].height() as u32,
+    save_strips(
+        &args.output,
+        width,
+        height,
         ColorType::Rgb8,
-    )
-    .expect("Couldn't store image");
-
-    for i in 0..c.buffers.len() {
-        image::save_buffer(
-            &format!("buffer-{}.png", i),
-            &buffers[i]
-                .to_f32()
-                .exponential(3.0)
-                .expose(1.3)
-                .to_u8()
-                .buffer(),
-            buffers[0].width() as u32,
-            buffers[0].height() as u32,
+        STRIP_ROWS,
+        |rows| {
+            let mut channel = |i: usize| -> Result<Buffer<u8>, Error> {
+                Ok(files[i]
+                    .read_rows(rows.clone())?
+                    .normalize(maxima[i])
+                    .exponential(r.exponential)
+                    .to_u8())
+            };
+            Ok(Buffer::join(
+                channel(r.channels[0])?,
+                channel(r.channels[1])?,
+                channel(r.channels[2])?,
+            )?
+            .flatten())
+        },
+    )?;
+
+    for i in 0..files.len() {
+        save_strips(
+            args.output
+                .with_file_name(format!("{}-{}.png", args.prefix, i)),
+            width,
+            height,
             ColorType::L8,
-        )
-        .expect("Couldn't store image");
+            STRIP_ROWS,
+            |rows| {
+                Ok(files[i]
+                    .read_rows(rows)?
+                    .normalize(maxima[i])
+                    .exponential(r.buffer_exponential)
+                    .expose(r.buffer_expose)
+                    .to_u8()
+                    .buffer()
+                    .clone())
+            },
+        )?;
     }
+    Ok(())
 }
@@ -0,0 +1,747 @@
+use num::complex::Complex;
+use rand::Rng;
+use rand_pcg::Pcg64Mcg;
+use serde::{Deserialize, Serialize};
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::buffer::Buffer;
+use crate::error::Error;
+use crate::float::{complex, Float};
+use crate::generate::{rand_complex, rand_complex_normal};
+
+/// A sampled value of c, or of z0 in the Julia mode, with the escape iteration and number of in-view hits of its orbit
+#[derive(Clone, Copy, Debug)]
+pub struct Sample<T> {
+    pub c: Complex<T>,
+    pub iterations: u32,
+    pub hits: u32,
+}
+
+/// An orbit to add to the buffers
+#[derive(Clone, Copy, Debug)]
+pub struct Splat<T> {
+    pub sample: Sample<T>,
+    /// Weight of the orbit, 1 for the unweighted samplers. The importance sampler divides by its
+    /// density relative to uniform sampling, so its weights are relative to one uniformly sampled
+    /// orbit. The mutation walk divides by the hits of the orbit, which leaves a global factor of
+    /// the total hits over the sampled region that is not known.
+    pub weight: f64,
+    /// Number of samples this orbit stands for
+    pub samples: u64,
+}
+
+/// Strategy for choosing the values of c, or of z0 in the Julia mode, whose orbits are accumulated
+pub trait Sampler<T: Float>: Send {
+    /// Next value of c to evaluate
+    fn propose(&mut self, rng: &mut Pcg64Mcg) -> Complex<T>;
+
+    /// Takes the evaluated proposal and pushes the orbits that should be added to the buffers
+    fn update(&mut self, proposal: Sample<T>, _rng: &mut Pcg64Mcg, splats: &mut Vec<Splat<T>>) {
+        if proposal.hits > 0 {
+            splats.push(Splat {
+                sample: proposal,
+                weight: 1.0,
+                samples: 1,
+            });
+        }
+    }
+
+    /// Pushes orbits that were held back, called at the end of the run
+    fn flush(&mut self, _splats: &mut Vec<Splat<T>>) {}
+
+    /// Acceptance statistics of samplers that can reject proposals
+    fn stats(&self) -> Option<SamplerStats> {
+        None
+    }
+
+    /// Whether proposals do not depend on the evaluation of earlier ones, so several of them can
+    /// be iterated together
+    fn is_independent(&self) -> bool {
+        true
+    }
+}
+
+/// Acceptance statistics of a sampler
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SamplerStats {
+    pub proposed: u64,
+    pub accepted: u64,
+    /// Deviation of normal steps after adaptation, before escape time scaling
+    pub deviation: Option<f64>,
+}
+impl SamplerStats {
+    pub fn acceptance(&self) -> f64 {
+        if self.proposed == 0 {
+            0.0
+        } else {
+            self.accepted as f64 / self.proposed as f64
+        }
+    }
+}
+
+/// Independent uniform samples over `min..max`
+pub struct Uniform<T> {
+    pub min: Complex<T>,
+    pub max: Complex<T>,
+}
+impl<T: Float> Sampler<T> for Uniform<T> {
+    fn propose(&mut self, rng: &mut Pcg64Mcg) -> Complex<T> {
+        rand_complex(self.min, self.max, rng)
+    }
+}
+
+/// One uniform sample per cell of a `strata`x`strata` grid over `min..max`, visiting the cells
+/// in turn
+pub struct Stratified<T> {
+    pub min: Complex<T>,
+    pub max: Complex<T>,
+    pub strata: u32,
+    pub index: u64,
+}
+impl<T: Float> Sampler<T> for Stratified<T> {
+    fn propose(&mut self, rng: &mut Pcg64Mcg) -> Complex<T> {
+        let strata = self.strata as u64;
+        let cell = self.index % (strata * strata);
+        self.index += 1;
+
+        let size = (self.max - self.min) / T::from_f64(self.strata as f64);
+        let min = self.min
+            + Complex::new(
+                T::from_f64((cell % strata) as f64) * size.re,
+                T::from_f64((cell / strata) as f64) * size.im,
+            );
+        rand_complex(min, min + size, rng)
+    }
+}
+
+/// The Halton sequence in bases 2 and 3 over `min..max`, shifted by a random offset. Every thread
+/// gets its own offset, and repeated runs do not sample the same points.
+pub struct Halton<T> {
+    pub min: Complex<T>,
+    pub max: Complex<T>,
+    pub index: u64,
+    pub shift: (f64, f64),
+}
+impl<T: Float> Halton<T> {
+    pub fn new(min: Complex<T>, max: Complex<T>, rng: &mut Pcg64Mcg) -> Halton<T> {
+        Halton {
+            min,
+            max,
+            index: 1,
+            shift: (rng.gen_range(0.0..1.0), rng.gen_range(0.0..1.0)),
+        }
+    }
+}
+impl<T: Float> Sampler<T> for Halton<T> {
+    fn propose(&mut self, _rng: &mut Pcg64Mcg) -> Complex<T> {
+        let x = (radical_inverse(2, self.index) + self.shift.0).fract();
+        let y = (radical_inverse(3, self.index) + self.shift.1).fract();
+        self.index += 1;
+
+        self.min
+            + Complex::new(
+                T::from_f64(x) * (self.max.re - self.min.re),
+                T::from_f64(y) * (self.max.im - self.min.im),
+            )
+    }
+}
+
+/// Mirrors the digits of `i` in `base` at the decimal point
+fn radical_inverse(base: u64, mut i: u64) -> f64 {
+    let mut result = 0.0;
+    let mut factor = 1.0 / base as f64;
+    while i > 0 {
+        result += (i % base) as f64 * factor;
+        i /= base;
+        factor /= base as f64;
+    }
+    result
+}
+
+/// Number of normal steps after which an adaptive walk adjusts its deviation
+const ADAPT_BATCH: u64 = 256;
+
+/// Metropolis-Hastings walk with the number of in-view hits of an orbit as target density. Every
+/// step either jumps to a uniform point within `min..max` with probability `jump`, or takes a
+/// normal step around the current position.
+pub struct Mutation<T> {
+    pub min: Complex<T>,
+    pub max: Complex<T>,
+    pub jump: f32,
+    /// Standard deviation of normal steps before adaptation
+    pub deviation: T,
+    /// Divide the deviation by the square root of the current orbit's escape time, so the walk
+    /// takes smaller steps close to the boundary of the set
+    pub escape_time_scaling: bool,
+    /// Acceptance rate of normal steps the deviation is adapted towards
+    pub target_acceptance: Option<f32>,
+    /// See [`crate::Config::splat_rejected`]
+    pub splat_rejected: bool,
+
+    /// Current position of the walk
+    pub current: Sample<T>,
+    /// Weight the current position has collected and the number of steps the walk stayed there,
+    /// splatted once the walk moves on or the run ends
+    pub pending_weight: f64,
+    pub pending_steps: u64,
+
+    /// Factor the adaptation applied to `deviation`
+    pub adaptation: f32,
+    /// Normal steps proposed and accepted in the current adaptation batch, and the number of
+    /// finished batches
+    pub batch_proposed: u64,
+    pub batch_accepted: u64,
+    pub batches: u64,
+    /// Whether the last proposal was a normal step
+    pub last_normal: bool,
+    pub stats: SamplerStats,
+}
+impl<T: Float> Mutation<T> {
+    pub fn new(
+        min: Complex<T>,
+        max: Complex<T>,
+        jump: f32,
+        deviation: T,
+        current: Complex<T>,
+    ) -> Mutation<T> {
+        Mutation {
+            min,
+            max,
+            jump,
+            deviation,
+            escape_time_scaling: false,
+            target_acceptance: None,
+            splat_rejected: false,
+
+            current: Sample {
+                c: current,
+                iterations: 0,
+                hits: 0,
+            },
+            pending_weight: 0.0,
+            pending_steps: 0,
+
+            adaptation: 1.0,
+            batch_proposed: 0,
+            batch_accepted: 0,
+            batches: 0,
+            last_normal: false,
+            stats: SamplerStats::default(),
+        }
+    }
+
+    /// Standard deviation of normal steps starting at `from`
+    pub fn step_deviation(&self, from: &Sample<T>) -> T {
+        let deviation = self.deviation * T::from_f64(self.adaptation as f64);
+        if self.escape_time_scaling {
+            deviation / T::from_f64(from.iterations.max(1) as f64).sqrt()
+        } else {
+            deviation
+        }
+    }
+
+    /// Probability density of proposing `to` from `from`
+    pub fn density(&self, from: &Sample<T>, to: Complex<T>) -> f64 {
+        let (min, max) = (self.min, self.max);
+        let jump = self.jump as f64;
+
+        let uniform = if min.re <= to.re && to.re < max.re && min.im <= to.im && to.im < max.im {
+            let area = (max.re - min.re).as_f64() * (max.im - min.im).as_f64();
+            jump / area
+        } else {
+            0.0
+        };
+        let deviation = self.step_deviation(from).as_f64();
+        let variance = deviation * deviation;
+        let distance = (to - from.c).norm_sqr().as_f64();
+        let normal = (1.0 - jump) * (-distance / (2.0 * variance)).exp()
+            / (2.0 * std::f64::consts::PI * variance);
+
+        uniform + normal
+    }
+
+    /// Probability of moving from `current` to `proposal`, the Metropolis-Hastings ratio of their
+    /// hits corrected by the densities of proposing one from the other
+    pub fn acceptance(&self, current: &Sample<T>, proposal: &Sample<T>) -> f64 {
+        if proposal.hits == 0 {
+            0.0
+        } else if current.hits == 0 {
+            1.0
+        } else {
+            let ratio = proposal.hits as f64 * self.density(proposal, current.c)
+                / (current.hits as f64 * self.density(current, proposal.c));
+            ratio.min(1.0)
+        }
+    }
+
+    /// Weight of an orbit with `hits` in-view points. The walk visits `c` proportionally to its
+    /// hits, dividing by them gives every `c` the same expected weight, but only up to the total
+    /// hits over the region, which normalize the walk's density and are not known.
+    fn weight(&self, hits: u32) -> f64 {
+        if hits == 0 {
+            0.0
+        } else {
+            1.0 / hits as f64
+        }
+    }
+
+    /// Moves the deviation towards the target acceptance rate after every batch of normal steps.
+    /// The adjustments shrink over time, so the walk still converges to its target density.
+    fn adapt(&mut self, accepted: bool) {
+        let target = if let Some(target) = self.target_acceptance {
+            target
+        } else {
+            return;
+        };
+        self.batch_proposed += 1;
+        self.batch_accepted += accepted as u64;
+        if self.batch_proposed < ADAPT_BATCH {
+            return;
+        }
+
+        self.batches += 1;
+        let rate = self.batch_accepted as f32 / self.batch_proposed as f32;
+        self.adaptation *= ((rate - target) / (self.batches as f32).sqrt()).exp();
+        self.adaptation = self.adaptation.clamp(1e-6, 1e6);
+        self.batch_proposed = 0;
+        self.batch_accepted = 0;
+    }
+}
+impl<T: Float> Sampler<T> for Mutation<T> {
+    fn propose(&mut self, rng: &mut Pcg64Mcg) -> Complex<T> {
+        self.last_normal = rng.gen_range(0.0..1.0) >= self.jump;
+        if self.last_normal {
+            rand_complex_normal(self.current.c, self.step_deviation(&self.current), rng)
+        } else {
+            rand_complex(self.min, self.max, rng)
+        }
+    }
+
+    fn update(&mut self, proposal: Sample<T>, rng: &mut Pcg64Mcg, splats: &mut Vec<Splat<T>>) {
+        let current = self.current;
+        let acceptance = self.acceptance(&current, &proposal);
+
+        if self.splat_rejected {
+            // Record both candidates with the probability of ending up at them
+            if acceptance > 0.0 {
+                splats.push(Splat {
+                    sample: proposal,
+                    weight: acceptance * self.weight(proposal.hits),
+                    samples: 0,
+                });
+            }
+            self.pending_weight += (1.0 - acceptance) * self.weight(current.hits);
+        }
+        let accepted = acceptance > 0.0 && rng.gen_range(0.0..1.0) < acceptance;
+        if accepted {
+            self.flush(splats);
+            self.current = proposal;
+        }
+        if !self.splat_rejected {
+            self.pending_weight += self.weight(self.current.hits);
+        }
+        self.pending_steps += 1;
+
+        self.stats.proposed += 1;
+        self.stats.accepted += accepted as u64;
+        if self.last_normal {
+            self.adapt(accepted);
+        }
+    }
+
+    fn flush(&mut self, splats: &mut Vec<Splat<T>>) {
+        splats.push(Splat {
+            sample: self.current,
+            weight: self.pending_weight,
+            samples: self.pending_steps,
+        });
+        self.pending_weight = 0.0;
+        self.pending_steps = 0;
+    }
+
+    fn stats(&self) -> Option<SamplerStats> {
+        Some(SamplerStats {
+            deviation: Some(self.deviation.as_f64() * self.adaptation as f64),
+            ..self.stats
+        })
+    }
+    fn is_independent(&self) -> bool {
+        false
+    }
+}
+
+/// Coarse density over c-space, stored as a .bread buffer whose region is the sampled area
+pub struct ImportanceMap<T> {
+    pub min: Complex<T>,
+    pub max: Complex<T>,
+    pub width: usize,
+    pub height: usize,
+    /// Cumulative weights of the cells in row-major order
+    cdf: Vec<f64>,
+}
+impl<T: Float> ImportanceMap<T> {
+    pub fn new(buffer: &Buffer<u32>, min: Complex<T>, max: Complex<T>) -> ImportanceMap<T> {
+        let mut total = 0.0;
+        let cdf = buffer
+            .buffer()
+            .iter()
+            .map(|w| {
+                total += *w as f64;
+                total
+            })
+            .collect();
+        ImportanceMap {
+            min,
+            max,
+            width: buffer.width(),
+            height: buffer.height(),
+            cdf,
+        }
+    }
+
+    pub fn load(file: impl AsRef<Path>) -> Result<ImportanceMap<T>, Error> {
+        let (header, buffer) = Buffer::<u32>::load(file)?;
+        Ok(ImportanceMap::new(
+            &buffer,
+            complex(header.min),
+            complex(header.max),
+        ))
+    }
+
+    fn total(&self) -> f64 {
+        self.cdf.last().copied().unwrap_or(0.0)
+    }
+
+    /// Weight of the cell containing `c` relative to the average cell
+    pub fn density(&self, c: Complex<T>) -> f64 {
+        let x = ((c.re - self.min.re) / (self.max.re - self.min.re)).as_f64() * self.width as f64;
+        let y = ((c.im - self.min.im) / (self.max.im - self.min.im)).as_f64() * self.height as f64;
+        if self.total() == 0.0
+            || x < 0.0
+            || y < 0.0
+            || x >= self.width as f64
+            || y >= self.height as f64
+        {
+            return 0.0;
+        }
+
+        let cell = y as usize * self.width + x as usize;
+        let previous = if cell == 0 { 0.0 } else { self.cdf[cell - 1] };
+        (self.cdf[cell] - previous) / self.total() * self.cdf.len() as f64
+    }
+
+    /// Draws a cell proportionally to its weight and a uniform point within it
+    pub fn sample(&self, rng: &mut Pcg64Mcg) -> Complex<T> {
+        let target = rng.gen_range(0.0..self.total());
+        let cell = self
+            .cdf
+            .partition_point(|w| *w <= target)
+            .min(self.cdf.len() - 1);
+
+        let size = Complex::new(
+            (self.max.re - self.min.re) / T::from_f64(self.width as f64),
+            (self.max.im - self.min.im) / T::from_f64(self.height as f64),
+        );
+        let min = self.min
+            + Complex::new(
+                T::from_f64((cell % self.width) as f64) * size.re,
+                T::from_f64((cell / self.width) as f64) * size.im,
+            );
+        rand_complex(min, min + size, rng)
+    }
+}
+
+/// Samples c proportionally to an [`ImportanceMap`], mixed with a fraction of uniform samples
+/// over the map's region so that cells without weight are still reached
+pub struct Importance<T> {
+    pub map: Arc<ImportanceMap<T>>,
+    pub uniform: f32,
+}
+impl<T: Float> Sampler<T> for Importance<T> {
+    fn propose(&mut self, rng: &mut Pcg64Mcg) -> Complex<T> {
+        if self.map.total() == 0.0 || rng.gen_range(0.0..1.0) < self.uniform {
+            rand_complex(self.map.min, self.map.max, rng)
+        } else {
+            self.map.sample(rng)
+        }
+    }
+
+    fn update(&mut self, proposal: Sample<T>, _rng: &mut Pcg64Mcg, splats: &mut Vec<Splat<T>>) {
+        // Density of the proposal relative to uniform sampling
+        let density = if self.map.total() == 0.0 {
+            1.0
+        } else {
+            let uniform = self.uniform as f64;
+            uniform + (1.0 - uniform) * self.map.density(proposal.c)
+        };
+        if proposal.hits > 0 && density > 0.0 {
+            splats.push(Splat {
+                sample: proposal,
+                weight: 1.0 / density,
+                samples: 1,
+            });
+        }
+    }
+}
+
+/// What the deviation of the mutation walk's normal steps is relative to
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MutateScale {
+    /// `mutate_deviation` is an absolute distance
+    #[default]
+    None,
+    /// `mutate_deviation` is a fraction of the view's width
+    View,
+    /// `mutate_deviation` is divided by the square root of the current orbit's escape time
+    EscapeTime,
+}
+
+/// Selects one of the built-in samplers in the config
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum SamplerConfig {
+    Uniform,
+    Stratified {
+        strata: u32,
+    },
+    Halton,
+    /// Metropolis-Hastings walk configured by the `mutate_*` fields and `splat_rejected`
+    #[default]
+    Mutation,
+    /// Importance sampling by a map of contributing c values over `min..max`, stored as .bread
+    /// file. The map is built by a pre-pass if the file does not exist or was built for another
+    /// region, view, resolution or other settings the orbits depend on.
+    Importance {
+        map: PathBuf,
+        /// Fraction of uniform samples
+        uniform: f32,
+        /// Side length of the grid built by the pre-pass
+        #[serde(default = "default_resolution")]
+        resolution: usize,
+        /// Orbits the pre-pass samples per grid cell
+        #[serde(default = "default_cell_samples")]
+        cell_samples: u32,
+    },
+}
+/// [`SamplerConfig`] without its settings, as recorded in the header
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SamplerKind {
+    Uniform,
+    Stratified,
+    Halton,
+    Mutation,
+    Importance,
+}
+
+impl SamplerConfig {
+    pub fn kind(&self) -> SamplerKind {
+        match self {
+            SamplerConfig::Uniform => SamplerKind::Uniform,
+            SamplerConfig::Stratified { .. } => SamplerKind::Stratified,
+            SamplerConfig::Halton => SamplerKind::Halton,
+            SamplerConfig::Mutation => SamplerKind::Mutation,
+            SamplerConfig::Importance { .. } => SamplerKind::Importance,
+        }
+    }
+
+    /// Whether the sampler prefers some orbits and gives them weights other than 1
+    pub fn is_weighted(&self) -> bool {
+        matches!(
+            self,
+            SamplerConfig::Mutation | SamplerConfig::Importance { .. }
+        )
+    }
+}
+fn default_resolution() -> usize {
+    128
+}
+fn default_cell_samples() -> u32 {
+    16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use num::complex::Complex32;
+    use rand::SeedableRng;
+
+    fn c(re: f32, im: f32) -> Complex32 {
+        Complex32::new(re, im)
+    }
+    fn sample(re: f32, im: f32, iterations: u32, hits: u32) -> Sample<f32> {
+        Sample {
+            c: c(re, im),
+            iterations,
+            hits,
+        }
+    }
+    fn mutation() -> Mutation<f32> {
+        Mutation {
+            current: sample(0.1, 0.2, 4, 10),
+            ..Mutation::new(c(-2.0, -2.0), c(2.0, 2.0), 0.3, 0.05, c(0.0, 0.0))
+        }
+    }
+
+    #[test]
+    fn density_matches_proposals() {
+        let mut mutation = mutation();
+        let mut rng = Pcg64Mcg::seed_from_u64(1);
+        let proposals = (0..400_000)
+            .map(|_| mutation.propose(&mut rng))
+            .collect::<Vec<_>>();
+
+        // Boxes around the current point, one deviation away and where only jumps land
+        for (center, size) in [
+            (c(0.1, 0.2), 0.02),
+            (c(0.15, 0.2), 0.02),
+            (c(0.065, 0.165), 0.02),
+            (c(1.5, -1.5), 0.5),
+        ] {
+            let half = size / 2.0;
+            let inside = proposals
+                .iter()
+                .filter(|c| (c.re - center.re).abs() < half && (c.im - center.im).abs() < half)
+                .count();
+            let measured = inside as f64 / (proposals.len() as f64 * (size * size) as f64);
+            let expected = mutation.density(&mutation.current, center);
+            assert!(
+                (measured / expected - 1.0).abs() < 0.1,
+                "density at {}: measured {}, expected {}",
+                center,
+                measured,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn acceptance_satisfies_detailed_balance() {
+        let mutation = mutation();
+        let pairs = [
+            (sample(0.1, 0.2, 4, 10), sample(0.15, 0.1, 9, 3)),
+            (sample(-0.7, 0.3, 100, 50), sample(-0.72, 0.35, 20, 80)),
+            (sample(0.3, -0.5, 1, 1), sample(1.9, 1.9, 64, 200)),
+        ];
+        for (x, y) in pairs {
+            // Flow from x to y equals the flow back under the target density of hits
+            let forward = x.hits as f64 * mutation.density(&x, y.c) * mutation.acceptance(&x, &y);
+            let backward = y.hits as f64 * mutation.density(&y, x.c) * mutation.acceptance(&y, &x);
+            assert!(
+                (forward - backward).abs() <= 1e-12 * forward.max(backward),
+                "{} != {}",
+                forward,
+                backward
+            );
+            assert!(forward > 0.0);
+        }
+        let empty = sample(0.0, 0.0, 0, 0);
+        assert_eq!(mutation.acceptance(&pairs[0].0, &empty), 0.0);
+        assert_eq!(mutation.acceptance(&empty, &pairs[0].0), 1.0);
+    }
+
+    #[test]
+    fn adaptation_reaches_the_target_acceptance() {
+        // Orbits only contribute within a small disk, so the initial steps are far too large
+        let mut mutation = Mutation {
+            target_acceptance: Some(0.4),
+            current: sample(0.0, 0.0, 1, 1),
+            ..Mutation::new(c(-2.0, -2.0), c(2.0, 2.0), 0.0, 1.0, c(0.0, 0.0))
+        };
+        let mut rng = Pcg64Mcg::seed_from_u64(4);
+        let mut splats = Vec::new();
+        let mut step = |mutation: &mut Mutation<f32>| {
+            let c = mutation.propose(&mut rng);
+            let hits = (c.norm_sqr() < 0.01) as u32;
+            mutation.update(sample(c.re, c.im, 1, hits), &mut rng, &mut splats);
+        };
+
+        for _ in 0..200_000 {
+            step(&mut mutation);
+        }
+        assert!(mutation.adaptation < 0.5, "{}", mutation.adaptation);
+
+        let before = mutation.stats;
+        for _ in 0..100_000 {
+            step(&mut mutation);
+        }
+        let rate = (mutation.stats.accepted - before.accepted) as f64
+            / (mutation.stats.proposed - before.proposed) as f64;
+        assert!((rate - 0.4).abs() < 0.05, "{}", rate);
+    }
+
+    #[test]
+    fn stratified_visits_every_cell() {
+        let mut rng = Pcg64Mcg::seed_from_u64(2);
+        let mut stratified = Stratified {
+            min: c(-2.0, -1.0),
+            max: c(2.0, 1.0),
+            strata: 4,
+            index: 0,
+        };
+        for _ in 0..3 {
+            let mut cells = (0..16)
+                .map(|_| {
+                    let c = stratified.propose(&mut rng);
+                    ((c.re + 2.0) as usize, ((c.im + 1.0) * 2.0) as usize)
+                })
+                .collect::<Vec<_>>();
+            cells.sort_unstable();
+            cells.dedup();
+            assert_eq!(cells.len(), 16);
+            assert!(cells.iter().all(|(x, y)| *x < 4 && *y < 4));
+        }
+    }
+
+    #[test]
+    fn halton_fills_the_region_evenly() {
+        assert_eq!(radical_inverse(2, 1), 0.5);
+        assert_eq!(radical_inverse(2, 6), 0.375);
+        assert!((radical_inverse(3, 5) - 7.0 / 9.0).abs() < 1e-15);
+
+        let mut rng = Pcg64Mcg::seed_from_u64(3);
+        let mut halton = Halton {
+            shift: (0.0, 0.0),
+            ..Halton::new(c(-2.0, -2.0), c(2.0, 2.0), &mut rng)
+        };
+        // Any 4 * 9 consecutive points put one point into every cell of a 4x9 grid
+        let mut counts = [[0; 9]; 4];
+        for _ in 0..36 {
+            let c = halton.propose(&mut rng);
+            assert!(c.re >= -2.0 && c.re < 2.0 && c.im >= -2.0 && c.im < 2.0);
+            // Points can lie on the lower edge of their cell, up to rounding
+            let x = (c.re + 2.0 + 1e-4) as usize;
+            let y = ((c.im + 2.0) * 2.25 + 1e-4) as usize;
+            counts[x][y] += 1;
+        }
+        assert!(counts.iter().flatten().all(|n| *n == 1), "{:?}", counts);
+    }
+
+    #[test]
+    fn importance_weights_undo_the_density() {
+        let mut buffer = Buffer::new_default(2, 1);
+        buffer.set(0, 0, 3);
+        buffer.set(1, 0, 1);
+        let map = Arc::new(ImportanceMap::new(&buffer, c(0.0, 0.0), c(2.0, 1.0)));
+        assert_eq!(map.density(c(0.5, 0.5)), 1.5);
+        assert_eq!(map.density(c(1.5, 0.5)), 0.5);
+        assert_eq!(map.density(c(2.5, 0.5)), 0.0);
+
+        let mut importance = Importance { map, uniform: 0.2 };
+        let mut rng = Pcg64Mcg::seed_from_u64(4);
+        let mut splats = vec![];
+        importance.update(sample(0.5, 0.5, 20, 5), &mut rng, &mut splats);
+        importance.update(sample(1.5, 0.5, 20, 5), &mut rng, &mut splats);
+        importance.update(sample(1.5, 0.5, 20, 0), &mut rng, &mut splats);
+        assert_eq!(splats.len(), 2);
+        assert!((splats[0].weight - 1.0 / 1.4).abs() < 1e-6);
+        assert!((splats[1].weight - 1.0 / 0.6).abs() < 1e-6);
+
+        let left = (0..10_000)
+            .filter(|_| importance.propose(&mut rng).re < 1.0)
+            .count();
+        assert!((left as f64 / 10_000.0 - 0.7).abs() < 0.02);
+    }
+}
@@ -0,0 +1,401 @@
+use num::complex::Complex64;
+
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::marker::PhantomData;
+use std::ops::Range;
+use std::path::Path;
+
+use crate::buffer::{data_size, Buffer};
+use crate::error::Error;
+use crate::header::{Element, ElementType, Header};
+
+/// Approximate number of elements [`BufferFile::max`] and the conversion of u32 files read at once
+const STRIP_ELEMENTS: usize = 1 << 22;
+
+/// Rectangle of pixels within a buffer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Tile {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+impl Tile {
+    /// Splits a `width`×`height` buffer into tiles of at most `size`×`size` pixels, row by row
+    pub fn split(width: usize, height: usize, size: usize) -> Vec<Tile> {
+        let mut tiles = Vec::new();
+        for y in (0..height).step_by(size) {
+            for x in (0..width).step_by(size) {
+                tiles.push(Tile {
+                    x,
+                    y,
+                    width: size.min(width - x),
+                    height: size.min(height - y),
+                });
+            }
+        }
+        tiles
+    }
+
+    /// Full rows `rows` of a buffer of width `width`
+    pub fn rows(rows: Range<usize>, width: usize) -> Tile {
+        Tile {
+            x: 0,
+            y: rows.start,
+            width,
+            height: rows.end - rows.start,
+        }
+    }
+
+    /// Part of the region `min..max` covered by a `width`×`height` buffer that lies inside the
+    /// tile. Neighbouring tiles share their edges exactly.
+    pub fn region(
+        &self,
+        min: Complex64,
+        max: Complex64,
+        width: usize,
+        height: usize,
+    ) -> (Complex64, Complex64) {
+        let lerp = |min: f64, max: f64, t: f64| min * (1.0 - t) + max * t;
+        let corner = |x: usize, y: usize| {
+            Complex64::new(
+                lerp(min.re, max.re, x as f64 / width as f64),
+                lerp(min.im, max.im, y as f64 / height as f64),
+            )
+        };
+        (
+            corner(self.x, self.y),
+            corner(self.x + self.width, self.y + self.height),
+        )
+    }
+}
+
+/// A .bread file that is read and written in place one tile at a time, for buffers that do not
+/// fit into memory. Unlike [`Buffer::store`], writes are not atomic.
+pub struct BufferFile<T> {
+    file: File,
+    header: Header,
+    width: usize,
+    height: usize,
+    /// Offset of the first element in the file
+    start: u64,
+    element: PhantomData<T>,
+}
+impl<T: Element> BufferFile<T> {
+    /// Creates a `width`×`height` buffer file filled with zeros, replacing an existing file
+    pub fn create(
+        file: impl AsRef<Path>,
+        header: Header,
+        width: usize,
+        height: usize,
+    ) -> Result<BufferFile<T>, Error> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(file)?;
+        let mut bytes = Vec::new();
+        header.write(&mut bytes, T::TYPE, width, height)?;
+        file.write_all(&bytes)?;
+        let start = bytes.len() as u64;
+        file.set_len(start + data_size::<T>(width, height)?)?;
+
+        Ok(BufferFile {
+            file,
+            header,
+            width,
+            height,
+            start,
+            element: PhantomData,
+        })
+    }
+
+    /// Opens an existing file for reading
+    pub fn open(file: impl AsRef<Path>) -> Result<BufferFile<T>, Error> {
+        BufferFile::open_with(OpenOptions::new().read(true).open(file)?)
+    }
+    /// Opens an existing file for reading and writing. A file of u32 counts is converted to `T`
+    /// first, like [`Buffer::load`] does.
+    pub fn open_mut(file: impl AsRef<Path>) -> Result<BufferFile<T>, Error> {
+        let file = file.as_ref();
+        if T::TYPE != ElementType::U32 && ElementType::of_file(file)? == ElementType::U32 {
+            convert_u32::<T>(file)?;
+        }
+        BufferFile::open_with(OpenOptions::new().read(true).write(true).open(file)?)
+    }
+    fn open_with(mut file: File) -> Result<BufferFile<T>, Error> {
+        let file_size = file.metadata()?.len();
+        let mut f = BufReader::new(&mut file);
+        let (header, element_type, width, height) = Header::read(&mut f)?;
+
+        if element_type != T::TYPE {
+            return Err(Error::ElementTypeMismatch {
+                expected: T::TYPE,
+                found: element_type,
+            });
+        }
+        let start = f.stream_position()?;
+        let expected = data_size::<T>(width, height)?;
+        let found = file_size - start;
+        if expected != found {
+            return Err(Error::FileSize { expected, found });
+        }
+
+        Ok(BufferFile {
+            file,
+            header,
+            width,
+            height,
+            start,
+            element: PhantomData,
+        })
+    }
+
+    pub fn header(&self) -> &Header {
+        &self.header
+    }
+    pub fn width(&self) -> usize {
+        self.width
+    }
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Replaces the header stored in the file
+    pub fn set_header(&mut self, header: Header) -> Result<(), Error> {
+        let mut bytes = Vec::new();
+        header.write(&mut bytes, T::TYPE, self.width, self.height)?;
+        self.file.seek(SeekFrom::Start(0))?;
+        self.file.write_all(&bytes)?;
+        self.header = header;
+        Ok(())
+    }
+
+    pub fn read_tile(&mut self, tile: &Tile) -> Result<Buffer<T>, Error> {
+        self.check_tile(tile)?;
+        let mut row = vec![0; tile.width * T::SIZE];
+        let mut buffer = Vec::with_capacity(tile.width * tile.height);
+        for y in tile.y..tile.y + tile.height {
+            self.file.seek(SeekFrom::Start(self.offset(tile.x, y)))?;
+            self.file.read_exact(&mut row)?;
+            let mut r = &row[..];
+            for _ in 0..tile.width {
+                buffer.push(T::read(&mut r)?);
+            }
+        }
+        Ok(Buffer::from_vec(tile.width, tile.height, buffer))
+    }
+    pub fn read_rows(&mut self, rows: Range<usize>) -> Result<Buffer<T>, Error> {
+        self.read_tile(&Tile::rows(rows, self.width))
+    }
+
+    /// Overwrites the pixels of `tile` with `buffer`, which has to have the tile's dimensions
+    pub fn write_tile(&mut self, tile: &Tile, buffer: &Buffer<T>) -> Result<(), Error> {
+        self.check_tile(tile)?;
+        if buffer.width() != tile.width || buffer.height() != tile.height {
+            return Err(Error::DimensionMismatch {
+                expected: (tile.width, tile.height),
+                found: (buffer.width(), buffer.height()),
+            });
+        }
+        let mut row = Vec::with_capacity(tile.width * T::SIZE);
+        for y in 0..tile.height {
+            row.clear();
+            for x in 0..tile.width {
+                buffer.get(x, y).write(&mut row)?;
+            }
+            self.file
+                .seek(SeekFrom::Start(self.offset(tile.x, tile.y + y)))?;
+            self.file.write_all(&row)?;
+        }
+        Ok(())
+    }
+
+    /// Largest element of the buffer, read in strips of rows
+    pub fn max(&mut self) -> Result<T, Error>
+    where
+        T: PartialOrd + Copy + Default,
+    {
+        let rows = (STRIP_ELEMENTS / self.width.max(1)).max(1);
+        let mut max = T::default();
+        for y in (0..self.height).step_by(rows) {
+            let strip = self.read_rows(y..(y + rows).min(self.height))?;
+            for x in strip.buffer() {
+                if *x > max {
+                    max = *x;
+                }
+            }
+        }
+        Ok(max)
+    }
+
+    fn offset(&self, x: usize, y: usize) -> u64 {
+        self.start + ((y * self.width + x) * T::SIZE) as u64
+    }
+    fn check_tile(&self, tile: &Tile) -> Result<(), Error> {
+        if tile.x + tile.width > self.width || tile.y + tile.height > self.height {
+            Err(Error::DimensionMismatch {
+                expected: (self.width, self.height),
+                found: (tile.x + tile.width, tile.y + tile.height),
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Replaces a file of u32 counts with one of elements of type `T`, converting a strip of rows at a
+/// time
+fn convert_u32<T: Element>(file: &Path) -> Result<(), Error> {
+    let mut source = BufferFile::<u32>::open(file)?;
+    let (width, height) = (source.width, source.height);
+    let mut temp = file.as_os_str().to_owned();
+    temp.push(".tmp");
+
+    let mut converted = BufferFile::<T>::create(&temp, source.header.clone(), width, height)?;
+    let rows = (STRIP_ELEMENTS / width.max(1)).max(1);
+    for y in (0..height).step_by(rows) {
+        let tile = Tile::rows(y..(y + rows).min(height), width);
+        let strip = source.read_tile(&tile)?;
+        let strip = strip.buffer().iter().map(|x| T::from_u32(*x)).collect();
+        converted.write_tile(&tile, &Buffer::from_vec(tile.width, tile.height, strip))?;
+    }
+    converted.file.sync_all()?;
+    fs::rename(&temp, file)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::header::tests::header;
+
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn temp_file(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("bread-tile-{}-{}.bread", std::process::id(), name))
+    }
+
+    #[test]
+    fn split_covers_the_buffer() {
+        let tiles = Tile::split(10, 7, 4);
+        assert_eq!(tiles.len(), 6);
+        assert_eq!(
+            tiles[5],
+            Tile {
+                x: 8,
+                y: 4,
+                width: 2,
+                height: 3
+            }
+        );
+        let mut covered = vec![0; 10 * 7];
+        for tile in &tiles {
+            for y in tile.y..tile.y + tile.height {
+                for x in tile.x..tile.x + tile.width {
+                    covered[y * 10 + x] += 1;
+                }
+            }
+        }
+        assert!(covered.iter().all(|&c| c == 1));
+    }
+
+    #[test]
+    fn regions_share_their_edges() {
+        let (min, max) = (Complex64::new(-2.0, -1.3), Complex64::new(1.1, 1.7));
+        let tiles = Tile::split(30, 20, 7);
+        for tile in &tiles {
+            let (tile_min, tile_max) = tile.region(min, max, 30, 20);
+            if let Some(right) = tiles
+                .iter()
+                .find(|t| t.x == tile.x + tile.width && t.y == tile.y)
+            {
+                assert_eq!(right.region(min, max, 30, 20).0.re, tile_max.re);
+            }
+            if let Some(below) = tiles
+                .iter()
+                .find(|t| t.y == tile.y + tile.height && t.x == tile.x)
+            {
+                assert_eq!(below.region(min, max, 30, 20).0.im, tile_max.im);
+            }
+            assert!(tile_min.re < tile_max.re && tile_min.im < tile_max.im);
+        }
+        assert_eq!(tiles[0].region(min, max, 30, 20).0, min);
+        assert_eq!(tiles.last().unwrap().region(min, max, 30, 20).1, max);
+    }
+
+    #[test]
+    fn file_round_trips_tiles() {
+        let path = temp_file("round-trip");
+        let mut file = BufferFile::<u32>::create(&path, header(), 9, 5).unwrap();
+        for (i, tile) in Tile::split(9, 5, 4).iter().enumerate() {
+            let values = (0..tile.width * tile.height)
+                .map(|p| (i * 100 + p) as u32)
+                .collect();
+            let buffer = Buffer::from_vec(tile.width, tile.height, values);
+            file.write_tile(tile, &buffer).unwrap();
+            assert_eq!(file.read_tile(tile).unwrap().buffer(), buffer.buffer());
+        }
+        let mut header = header();
+        header.samples = 7;
+        file.set_header(header.clone()).unwrap();
+        drop(file);
+
+        let mut file = BufferFile::<u32>::open(&path).unwrap();
+        assert_eq!(file.header(), &header);
+        assert_eq!(file.max().unwrap(), 500);
+        let rows = file.read_rows(1..3).unwrap();
+        let outside = Tile {
+            x: 6,
+            y: 2,
+            width: 4,
+            height: 1,
+        };
+        assert!(matches!(
+            file.read_tile(&outside),
+            Err(Error::DimensionMismatch { .. })
+        ));
+
+        let (loaded_header, loaded) = Buffer::<u32>::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(loaded_header, header);
+        assert_eq!(&loaded.buffer()[9..27], rows.buffer().as_slice());
+        // Tile 1 starts at (4, 0)
+        assert_eq!(*loaded.get(4, 0), 100);
+    }
+
+    #[test]
+    fn open_checks_the_size() {
+        let path = temp_file("size");
+        BufferFile::<u32>::create(&path, header(), 4, 4).unwrap();
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(file.metadata().unwrap().len() - 1).unwrap();
+        let opened = BufferFile::<u32>::open(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(matches!(opened, Err(Error::FileSize { .. })));
+    }
+
+    #[test]
+    fn open_mut_converts_u32_files() {
+        let path = temp_file("convert");
+        let tile = Tile::rows(0..2, 3);
+        let buffer = Buffer::from_vec(3, 2, vec![0, 1, 2, 3, 4, u32::MAX]);
+        let mut file = BufferFile::<u32>::create(&path, header(), 3, 2).unwrap();
+        file.write_tile(&tile, &buffer).unwrap();
+        drop(file);
+
+        let converted = BufferFile::<f64>::open_mut(&path).and_then(|mut f| f.read_tile(&tile));
+        let element_type = ElementType::of_file(&path);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            converted.unwrap().buffer(),
+            &[0.0, 1.0, 2.0, 3.0, 4.0, u32::MAX as f64]
+        );
+        assert_eq!(element_type.unwrap(), ElementType::F64);
+    }
+}